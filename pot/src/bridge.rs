@@ -1,6 +1,8 @@
 use crate::error::PotError;
+use crate::util::parse_assignments;
 use crate::Result;
 use ipnet::IpNet;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::net::IpAddr;
 use std::str::FromStr;
@@ -80,23 +82,13 @@ struct PartialBridgeConf {
 impl FromStr for PartialBridgeConf {
     type Err = std::convert::Infallible;
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        use crate::util::get_value;
-        let lines: Vec<String> = s
-            .to_string()
-            .lines()
-            .map(|x| x.trim().to_string())
-            .filter(|x| !x.starts_with('#'))
-            .collect();
         let mut result = PartialBridgeConf::default();
-        for linestr in &lines {
-            if linestr.starts_with("name=") {
-                result.name = get_value(linestr);
-            }
-            if linestr.starts_with("net=") {
-                result.network = get_value(linestr);
-            }
-            if linestr.starts_with("gateway=") {
-                result.gateway = get_value(linestr);
+        for (name, value) in parse_assignments(s, &mut HashMap::new()) {
+            match name.as_str() {
+                "name" => result.name = value.parse().ok(),
+                "net" => result.network = value.parse().ok(),
+                "gateway" => result.gateway = value.parse().ok(),
+                _ => (),
             }
         }
         Ok(result)
@@ -168,4 +160,13 @@ mod tests {
         let uut = BridgeConf::from_str("net=10.192.0.24/29\ngateway=10.192.0.25\nname=test-bridge");
         assert!(uut.is_ok());
     }
+
+    #[test]
+    fn bridge_conf_fromstr_021() {
+        let uut = BridgeConf::from_str(
+            "# bridge\nnet=\"10.192.0.24/29\"\ngateway='10.192.0.25' # gw\nname=test-bridge",
+        );
+        assert!(uut.is_ok());
+        assert_eq!(uut.unwrap().name, "test-bridge");
+    }
 }
@@ -0,0 +1,33 @@
+use crate::error::PotError;
+use crate::Result;
+use std::process::{Command, Stdio};
+
+/// An abstraction over the execution of external commands
+///
+/// It allows to replace the real system commands with canned outputs in tests
+pub trait CommandRunner {
+    /// Execute the program with the given arguments, returning its standard output
+    fn run(&self, program: &str, args: &[&str]) -> Result<String>;
+}
+
+/// The runner executing the commands on the system
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<String> {
+        let output = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()?;
+        if !output.status.success() {
+            return Err(PotError::CommandError(
+                format!("{} {}", program, args.join(" ")),
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    }
+}
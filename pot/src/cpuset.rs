@@ -0,0 +1,162 @@
+use crate::error::PotError;
+use crate::Result;
+
+/// Parse a list of CPUs, as accepted by cpuset -l and printed by cpuset -g
+///
+/// The elements are separated by commas and can be single CPUs or ranges: "0-3, 8, 10-11"
+pub fn parse_cpulist(s: &str) -> Result<Vec<u32>> {
+    let mut result = Vec::new();
+    for element in s.split(',').map(str::trim) {
+        let error = || PotError::CpusetError(s.to_string());
+        match element.split_once('-') {
+            Some((first, last)) => {
+                let first: u32 = first.trim().parse().map_err(|_| error())?;
+                let last: u32 = last.trim().parse().map_err(|_| error())?;
+                if first > last {
+                    return Err(error());
+                }
+                result.extend(first..=last);
+            }
+            None => result.push(element.parse().map_err(|_| error())?),
+        }
+    }
+    result.sort_unstable();
+    result.dedup();
+    Ok(result)
+}
+
+/// Format a list of CPUs in the compact form, using ranges for consecutive CPUs: "0-3,8,10-11"
+pub fn format_cpulist(cpus: &[u32]) -> String {
+    let mut cpus = cpus.to_vec();
+    cpus.sort_unstable();
+    cpus.dedup();
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for cpu in cpus {
+        match ranges.last_mut() {
+            Some((_first, last)) if *last + 1 == cpu => *last = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Parse the output of cpuset -g, returning the CPU mask
+///
+/// The output can contain the domain policy line, that is ignored:
+/// jail 1 mask: 0-3, 8-11
+/// jail 1 domain policy: first-touch mask: 0
+pub fn parse_cpuset_output(output: &str) -> Result<Vec<u32>> {
+    let line = output
+        .lines()
+        .find(|line| line.contains(" mask: ") && !line.contains(" domain policy: "))
+        .ok_or_else(|| PotError::CpusetError(output.to_string()))?;
+    let (_subject, mask) = line
+        .rsplit_once("mask:")
+        .ok_or_else(|| PotError::CpusetError(line.to_string()))?;
+    parse_cpulist(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::quickcheck;
+
+    #[test]
+    fn parse_cpulist_001() {
+        assert_eq!(parse_cpulist("0").unwrap(), vec![0]);
+        assert_eq!(parse_cpulist("0, 1, 2, 3").unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(
+            parse_cpulist("0-3, 8-11").unwrap(),
+            vec![0, 1, 2, 3, 8, 9, 10, 11]
+        );
+        assert_eq!(parse_cpulist("5,1-2,2").unwrap(), vec![1, 2, 5]);
+    }
+
+    #[test]
+    fn parse_cpulist_002() {
+        assert!(parse_cpulist("").is_err());
+        assert!(parse_cpulist("0,").is_err());
+        assert!(parse_cpulist("3-1").is_err());
+        assert!(parse_cpulist("0-").is_err());
+        assert!(parse_cpulist("a").is_err());
+    }
+
+    #[test]
+    fn format_cpulist_001() {
+        assert_eq!(format_cpulist(&[]), "");
+        assert_eq!(format_cpulist(&[3]), "3");
+        assert_eq!(format_cpulist(&[0, 1]), "0-1");
+        assert_eq!(format_cpulist(&[11, 0, 1, 2, 3, 8, 10, 9]), "0-3,8-11");
+        assert_eq!(format_cpulist(&[0, 2, 4, 5]), "0,2,4-5");
+    }
+
+    #[test]
+    fn parse_cpuset_output_001() {
+        let output =
+            "jail 1 mask: 0, 1, 2, 3, 4, 5, 6, 7\njail 1 domain policy: first-touch mask: 0";
+        assert_eq!(
+            parse_cpuset_output(output).unwrap(),
+            vec![0, 1, 2, 3, 4, 5, 6, 7]
+        );
+        let output = "jail 1 domain policy: first-touch mask: 0\njail 1 mask: 0-3, 8-11\n";
+        assert_eq!(
+            parse_cpuset_output(output).unwrap(),
+            vec![0, 1, 2, 3, 8, 9, 10, 11]
+        );
+        let output = "pid 1234 mask: 2\n";
+        assert_eq!(parse_cpuset_output(output).unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn parse_cpuset_output_002() {
+        assert!(parse_cpuset_output("").is_err());
+        assert!(parse_cpuset_output("no jail mask").is_err());
+        assert!(parse_cpuset_output("jail 1 domain policy: first-touch mask: 0").is_err());
+        assert!(parse_cpuset_output("jail 1 mask: 0, x").is_err());
+    }
+
+    fn normalize(cpus: &[u16]) -> Vec<u32> {
+        let mut result: Vec<u32> = cpus.iter().map(|x| u32::from(*x % 256)).collect();
+        result.sort_unstable();
+        result.dedup();
+        result
+    }
+
+    quickcheck! {
+        fn cpulist_roundtrip(cpus: Vec<u16>) -> bool {
+            let cpus = normalize(&cpus);
+            cpus.is_empty() || parse_cpulist(&format_cpulist(&cpus)).unwrap() == cpus
+        }
+
+        fn cpulist_compact(cpus: Vec<u16>) -> bool {
+            let cpus = normalize(&cpus);
+            let elements = cpus.windows(2).filter(|x| x[0] + 1 != x[1]).count() + 1;
+            cpus.is_empty() || format_cpulist(&cpus).split(',').count() == elements
+        }
+
+        fn cpuset_output_roundtrip(cpus: Vec<u16>, domain_first: bool) -> bool {
+            let cpus = normalize(&cpus);
+            if cpus.is_empty() {
+                return true;
+            }
+            let mask = format!("jail 7 mask: {}", format_cpulist(&cpus).replace(',', ", "));
+            let domain = "jail 7 domain policy: round-robin mask: 0";
+            let output = if domain_first {
+                format!("{}\n{}\n", domain, mask)
+            } else {
+                format!("{}\n{}\n", mask, domain)
+            };
+            parse_cpuset_output(&output).unwrap() == cpus
+        }
+    }
+}
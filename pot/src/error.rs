@@ -16,4 +16,16 @@ pub enum PotError {
     JlsError,
     #[error("Invalid bridge configuration")]
     BridgeConfError,
+    #[error("Command {0} failed: {1}")]
+    CommandError(String, String),
+    #[error("Invalid CPU topology: {0}")]
+    TopologyError(String),
+    #[error("Invalid rctl output: {0}")]
+    RctlError(String),
+    #[error("Invalid cpuset: {0}")]
+    CpusetError(String),
+    #[error("Invalid configuration value: {0}")]
+    ConfValueError(String),
+    #[error("{0}")]
+    IpamError(#[from] crate::ipam::IpamError),
 }
@@ -0,0 +1,315 @@
+use crate::command::CommandRunner;
+use crate::Result;
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+/// An interface of the host, with its addresses
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HostInterface {
+    pub name: String,
+    /// The addresses, with the prefix length of their network
+    pub addresses: Vec<IpNet>,
+    /// The remote addresses of the point to point links (tun, gif), as host networks
+    pub peers: Vec<IpNet>,
+}
+
+/// A route of the host routing table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostRoute {
+    pub destination: IpNet,
+    pub gateway: String,
+    pub netif: String,
+}
+
+/// A host network overlapping a pot network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostConflict {
+    pub network: IpNet,
+    pub description: String,
+}
+
+/// The networks configured on the host
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HostNetworks {
+    pub interfaces: Vec<HostInterface>,
+    pub routes: Vec<HostRoute>,
+}
+
+impl HostNetworks {
+    /// Read the interfaces (ifconfig) and the routing table (netstat -rn) of the host
+    pub fn from_system(runner: &dyn CommandRunner) -> Result<HostNetworks> {
+        let ifconfig = runner.run("/sbin/ifconfig", &[])?;
+        let netstat = runner.run("/usr/bin/netstat", &["-rn"])?;
+        Ok(HostNetworks {
+            interfaces: parse_ifconfig(&ifconfig),
+            routes: parse_netstat_routes(&netstat),
+        })
+    }
+
+    /// The interface networks and the routes overlapping the network
+    ///
+    /// The interfaces owning one of the pot addresses (like the bridge gateways) belong to
+    /// pot, so they are ignored, as the routes using them and the host routes of the local
+    /// addresses
+    pub fn get_conflicts(&self, network: &IpNet, pot_addresses: &[IpAddr]) -> Vec<HostConflict> {
+        let overlaps = |x: &IpNet| x.contains(&network.network()) || network.contains(&x.network());
+        let pot_interfaces: Vec<&str> = self
+            .interfaces
+            .iter()
+            .filter(|i| {
+                i.addresses
+                    .iter()
+                    .any(|a| pot_addresses.contains(&a.addr()))
+            })
+            .map(|i| i.name.as_str())
+            .collect();
+        let local_addresses: Vec<IpAddr> = self
+            .interfaces
+            .iter()
+            .flat_map(|i| i.addresses.iter().map(IpNet::addr))
+            .collect();
+        let mut result = Vec::new();
+        for interface in &self.interfaces {
+            if pot_interfaces.contains(&interface.name.as_str()) {
+                continue;
+            }
+            for address in interface.addresses.iter().filter(|x| overlaps(&x.trunc())) {
+                result.push(HostConflict {
+                    network: address.trunc(),
+                    description: format!("interface {} address {}", interface.name, address),
+                });
+            }
+            for peer in interface.peers.iter().filter(|x| overlaps(x)) {
+                result.push(HostConflict {
+                    network: *peer,
+                    description: format!("interface {} peer {}", interface.name, peer.addr()),
+                });
+            }
+        }
+        for route in &self.routes {
+            if pot_interfaces.contains(&route.netif.as_str())
+                || (route.destination.prefix_len() == route.destination.max_prefix_len()
+                    && local_addresses.contains(&route.destination.addr()))
+            {
+                continue;
+            }
+            if overlaps(&route.destination) {
+                result.push(HostConflict {
+                    network: route.destination,
+                    description: format!(
+                        "route to {} via {} ({})",
+                        route.destination, route.gateway, route.netif
+                    ),
+                });
+            }
+        }
+        result
+    }
+}
+
+/// Parse the output of ifconfig
+pub fn parse_ifconfig(output: &str) -> Vec<HostInterface> {
+    let mut result: Vec<HostInterface> = Vec::new();
+    for line in output.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            if let Some((name, _rest)) = line.split_once(": ") {
+                result.push(HostInterface {
+                    name: name.to_string(),
+                    ..Default::default()
+                });
+            }
+            continue;
+        }
+        let Some(interface) = result.last_mut() else {
+            continue;
+        };
+        let mut fields: Vec<&str> = line.split_whitespace().collect();
+        // a point to point address, like inet 10.8.0.2 --> 10.8.0.1 netmask 0xffffff00
+        if fields.get(2) == Some(&"-->") && fields.len() > 3 {
+            let peer = fields[3].split('%').next().unwrap_or_default();
+            if let Ok(peer) = peer.parse::<IpAddr>() {
+                interface.peers.push(IpNet::from(peer));
+            }
+            fields.drain(2..4);
+        }
+        let address = match fields.as_slice() {
+            ["inet", address, "netmask", netmask, ..] => {
+                let netmask = netmask.trim_start_matches("0x");
+                match (address.parse(), u32::from_str_radix(netmask, 16)) {
+                    (Ok(address), Ok(netmask)) => {
+                        IpNet::new(IpAddr::V4(address), netmask.count_ones() as u8).ok()
+                    }
+                    _ => None,
+                }
+            }
+            ["inet6", address, "prefixlen", prefix_len, ..] => {
+                let address = address.split('%').next().unwrap_or_default();
+                match (address.parse(), prefix_len.parse()) {
+                    (Ok(address), Ok(prefix_len)) => {
+                        IpNet::new(IpAddr::V6(address), prefix_len).ok()
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        if let Some(address) = address {
+            interface.addresses.push(address);
+        }
+    }
+    result
+}
+
+/// Parse a destination of netstat -rn; IPv4 networks can be abbreviated, like 10.8/16
+fn parse_destination(destination: &str) -> Option<IpNet> {
+    let (address, prefix_len) = match destination.split_once('/') {
+        Some((address, prefix_len)) => (address, Some(prefix_len.parse().ok()?)),
+        None => (destination, None),
+    };
+    // remove the scope of the link local addresses
+    let address = address.split('%').next()?;
+    let address: IpAddr = if address.contains(':') {
+        address.parse().ok()?
+    } else {
+        let mut octets: Vec<&str> = address.split('.').collect();
+        if octets.len() > 4 {
+            return None;
+        }
+        octets.resize(4, "0");
+        octets.join(".").parse().ok()?
+    };
+    let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+    IpNet::new(address, prefix_len.unwrap_or(max_prefix_len)).ok()
+}
+
+/// Parse the output of netstat -rn; the default routes are ignored
+pub fn parse_netstat_routes(output: &str) -> Vec<HostRoute> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 || fields[0] == "default" {
+                return None;
+            }
+            Some(HostRoute {
+                destination: parse_destination(fields[0])?,
+                gateway: fields[1].to_string(),
+                netif: fields[3].to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IFCONFIG: &str =
+        "em0: flags=8843<UP,BROADCAST,RUNNING,SIMPLEX,MULTICAST> metric 0 mtu 1500
+	options=481249b<RXCSUM,TXCSUM,VLAN_MTU,VLAN_HWTAGGING,VLAN_HWCSUM,LRO,WOL_MAGIC>
+	ether 00:0c:29:3a:7b:1c
+	inet 192.168.1.10 netmask 0xffffff00 broadcast 192.168.1.255
+	inet6 fe80::20c:29ff:fe3a:7b1c%em0 prefixlen 64 scopeid 0x1
+	media: Ethernet autoselect (1000baseT <full-duplex>)
+	status: active
+lo0: flags=8049<UP,LOOPBACK,RUNNING,MULTICAST> metric 0 mtu 16384
+	inet6 ::1 prefixlen 128
+	inet 127.0.0.1 netmask 0xff000000
+bridge0: flags=8843<UP,BROADCAST,RUNNING,SIMPLEX,MULTICAST> metric 0 mtu 1500
+	inet 10.192.0.1 netmask 0xffc00000 broadcast 10.255.255.255
+tun0: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> metric 0 mtu 1500
+	inet 10.8.0.2 --> 10.8.0.1 netmask 0xffffff00
+wg0: flags=80c1<UP,RUNNING,NOARP,MULTICAST> metric 0 mtu 1420
+	inet 10.200.0.1 netmask 0xffff0000
+";
+
+    const NETSTAT: &str = "Routing tables
+
+Internet:
+Destination        Gateway            Flags     Netif Expire
+default            192.168.1.1        UGS         em0
+10.8/16            10.8.0.1           UGS        tun0
+10.192.0.0/10      link#3             U       bridge0
+10.192.0.1         link#3             UHS         lo0
+127.0.0.1          link#2             UH          lo0
+172.16.5.0/24      192.168.1.254      UGS         em0
+192.168.1.0/24     link#1             U           em0
+
+Internet6:
+Destination                       Gateway                       Flags     Netif Expire
+::/96                             ::1                           UGRS        lo0
+::1                               link#2                        UHS         lo0
+fe80::%lo0/64                     link#2                        U           lo0
+";
+
+    #[test]
+    fn parse_ifconfig_001() {
+        let uut = parse_ifconfig(IFCONFIG);
+        assert_eq!(uut.len(), 5);
+        assert_eq!(uut[0].name, "em0");
+        assert_eq!(
+            uut[0].addresses,
+            vec![
+                "192.168.1.10/24".parse::<IpNet>().unwrap(),
+                "fe80::20c:29ff:fe3a:7b1c/64".parse::<IpNet>().unwrap()
+            ]
+        );
+        assert_eq!(uut[1].addresses.len(), 2);
+        assert_eq!(
+            uut[2].addresses,
+            vec!["10.192.0.1/10".parse::<IpNet>().unwrap()]
+        );
+        assert_eq!(
+            uut[3].addresses,
+            vec!["10.8.0.2/24".parse::<IpNet>().unwrap()]
+        );
+        assert_eq!(uut[3].peers, vec!["10.8.0.1/32".parse::<IpNet>().unwrap()]);
+        assert!(uut[0].peers.is_empty());
+        assert!(parse_ifconfig("").is_empty());
+    }
+
+    #[test]
+    fn parse_netstat_routes_001() {
+        let uut = parse_netstat_routes(NETSTAT);
+        assert_eq!(uut.len(), 9);
+        assert_eq!(
+            uut[0],
+            HostRoute {
+                destination: "10.8.0.0/16".parse().unwrap(),
+                gateway: "10.8.0.1".to_string(),
+                netif: "tun0".to_string(),
+            }
+        );
+        assert_eq!(uut[2].destination, "10.192.0.1/32".parse().unwrap());
+        assert_eq!(uut[8].destination, "fe80::/64".parse().unwrap());
+    }
+
+    #[test]
+    fn get_conflicts_001() {
+        let uut = HostNetworks {
+            interfaces: parse_ifconfig(IFCONFIG),
+            routes: parse_netstat_routes(NETSTAT),
+        };
+        let pot_addresses = vec!["10.192.0.1".parse().unwrap()];
+        let result = uut.get_conflicts(&"10.192.0.0/10".parse().unwrap(), &pot_addresses);
+        assert_eq!(
+            result,
+            vec![HostConflict {
+                network: "10.200.0.0/16".parse().unwrap(),
+                description: "interface wg0 address 10.200.0.1/16".to_string(),
+            }]
+        );
+        let result = uut.get_conflicts(&"172.16.0.0/16".parse().unwrap(), &pot_addresses);
+        assert_eq!(result.len(), 1);
+        assert_eq!(
+            result[0].description,
+            "route to 172.16.5.0/24 via 192.168.1.254 (em0)"
+        );
+        let result = uut.get_conflicts(&"10.0.0.0/8".parse().unwrap(), &[]);
+        assert_eq!(result.len(), 6);
+        assert!(result.contains(&HostConflict {
+            network: "10.8.0.1/32".parse().unwrap(),
+            description: "interface tun0 peer 10.8.0.1".to_string(),
+        }));
+    }
+}
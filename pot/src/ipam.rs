@@ -0,0 +1,886 @@
+use crate::bridge::{get_bridges_list, BridgeConf};
+use crate::{get_pot_conf_list, get_pot_list, NetType, PotConf, PotSystemConfig, Result};
+use ipnet::IpNet;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The errors returned validating an address
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum IpamError {
+    #[error("Address {0} already in use ({1})")]
+    AlreadyInUse(IpAddr, AddressRole),
+    #[error("Address {0} outside the network {1}")]
+    OutsideNetwork(IpAddr, IpNet),
+    #[error("Address {0} inside the forbidden network {1}")]
+    ForbiddenNetwork(IpAddr, IpNet),
+    #[error("Bridge {0} not found")]
+    BridgeNotFound(String),
+    #[error("Pot {0} not found")]
+    PotNotFound(String),
+    #[error("Unknown placement {0} (lowest, highest or best-fit)")]
+    UnknownPlacement(String),
+    #[error("Invalid reservation at line {0}: {1}")]
+    InvalidReservation(usize, String),
+}
+
+/// Why an address of the address space is not available
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressRole {
+    /// The network address
+    Network,
+    /// The broadcast address
+    Broadcast,
+    /// The default gateway of the pot network
+    Gateway,
+    /// The DNS pot
+    Dns(String),
+    /// The address of a pot
+    Pot(String),
+    /// The network address of a bridge
+    BridgeNetwork(String),
+    /// The broadcast address of a bridge
+    BridgeBroadcast(String),
+    /// The gateway of a bridge
+    BridgeGateway(String),
+    /// An address of the network of a bridge, not usable outside the bridge
+    BridgeAllocated(String),
+    /// An address reserved manually, with its description
+    Reserved(String),
+}
+
+impl std::fmt::Display for AddressRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddressRole::Network => write!(f, "network"),
+            AddressRole::Broadcast => write!(f, "broadcast"),
+            AddressRole::Gateway => write!(f, "default gateway"),
+            AddressRole::Dns(name) | AddressRole::Pot(name) => write!(f, "{}", name),
+            AddressRole::BridgeNetwork(name) => write!(f, "{} bridge - network", name),
+            AddressRole::BridgeBroadcast(name) => write!(f, "{} bridge - broadcast", name),
+            AddressRole::BridgeGateway(name) => write!(f, "{} bridge - gateway", name),
+            AddressRole::BridgeAllocated(name) => {
+                write!(f, "{} bridge - allocated address", name)
+            }
+            AddressRole::Reserved(description) => write!(f, "reserved - {}", description),
+        }
+    }
+}
+
+/// Where a new network is placed among the free blocks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placement {
+    Lowest,
+    Highest,
+    /// The smallest free block
+    BestFit,
+}
+
+impl FromStr for Placement {
+    type Err = IpamError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "lowest" => Ok(Placement::Lowest),
+            "highest" => Ok(Placement::Highest),
+            "best-fit" => Ok(Placement::BestFit),
+            _ => Err(IpamError::UnknownPlacement(s.to_string())),
+        }
+    }
+}
+
+/// The addresses of a network, with their role, and the forbidden networks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressSpace {
+    pub network: IpNet,
+    addresses: BTreeMap<IpAddr, AddressRole>,
+    /// The networks whose addresses are all used, like the bridge ones
+    ranges: Vec<(IpNet, AddressRole)>,
+    forbidden: Vec<IpNet>,
+}
+
+impl AddressSpace {
+    /// An empty address space
+    pub fn new(network: IpNet) -> AddressSpace {
+        AddressSpace {
+            network,
+            addresses: BTreeMap::new(),
+            ranges: Vec::new(),
+            forbidden: Vec::new(),
+        }
+    }
+
+    /// The address space of the pot network, including the manual reservations
+    pub fn from_system(conf: &PotSystemConfig) -> Result<AddressSpace> {
+        AddressSpace::from_system_for(conf, None)
+    }
+
+    /// The address space of the pot network, as seen by a pot being reconfigured
+    ///
+    /// The current addresses of the pot, including the DNS one, are not considered used
+    pub fn from_system_for(conf: &PotSystemConfig, for_pot: Option<&str>) -> Result<AddressSpace> {
+        let bridges = get_bridges_list(conf)?;
+        let pots = get_other_pots(conf, for_pot)?;
+        let mut result = AddressSpace::with_pots(conf, &bridges, &pots);
+        if let (Some(dns), Some(pot_name)) = (&conf.dns, for_pot) {
+            if dns.pot_name == pot_name {
+                result.addresses.remove(&dns.ip);
+            }
+        }
+        result.add_reservations(&get_reservations(conf)?);
+        Ok(result)
+    }
+
+    /// The address space of a bridge
+    pub fn from_bridge(conf: &PotSystemConfig, bridge_name: &str) -> Result<AddressSpace> {
+        AddressSpace::from_bridge_for(conf, bridge_name, None)
+    }
+
+    /// The address space of a bridge, as seen by a pot being reconfigured
+    pub fn from_bridge_for(
+        conf: &PotSystemConfig,
+        bridge_name: &str,
+        for_pot: Option<&str>,
+    ) -> Result<AddressSpace> {
+        let bridges = get_bridges_list(conf)?;
+        let bridge = bridges
+            .iter()
+            .find(|x| x.name == bridge_name)
+            .ok_or_else(|| IpamError::BridgeNotFound(bridge_name.to_string()))?;
+        let pots = get_other_pots(conf, for_pot)?;
+        let mut result = AddressSpace::with_bridge_pots(conf, bridge, &pots);
+        result.add_reservations(&get_reservations(conf)?);
+        Ok(result)
+    }
+
+    /// The address space of the pot network, given the bridges and the pots
+    ///
+    /// All the addresses of the bridges are reserved, the VPN networks are forbidden
+    pub fn with_pots(
+        conf: &PotSystemConfig,
+        bridges: &[BridgeConf],
+        pots: &[PotConf],
+    ) -> AddressSpace {
+        let mut result = AddressSpace::new(conf.network);
+        result.forbidden = conf.vpn_networks.clone();
+        result.insert(conf.network.network(), AddressRole::Network);
+        result.insert(conf.network.broadcast(), AddressRole::Broadcast);
+        result.insert(conf.gateway, AddressRole::Gateway);
+        if let Some(dns) = &conf.dns {
+            result.insert(dns.ip, AddressRole::Dns(dns.pot_name.clone()));
+        }
+        for pot in pots {
+            if let (NetType::PublicBridge | NetType::PrivateBridge, Some(ip)) =
+                (&pot.network_type, pot.ip_addr)
+            {
+                result.insert(ip, AddressRole::Pot(pot.name.clone()));
+            }
+        }
+        for bridge in bridges {
+            result.add_bridge(bridge);
+        }
+        result
+    }
+
+    /// The address space of a bridge, given the pots
+    pub fn with_bridge_pots(
+        conf: &PotSystemConfig,
+        bridge: &BridgeConf,
+        pots: &[PotConf],
+    ) -> AddressSpace {
+        let mut result = AddressSpace::new(bridge.network);
+        result.forbidden = conf.vpn_networks.clone();
+        result.insert_bridge(bridge);
+        for pot in pots {
+            if let (NetType::PublicBridge | NetType::PrivateBridge, Some(ip)) =
+                (&pot.network_type, pot.ip_addr)
+            {
+                if bridge.network.contains(&ip) {
+                    result.insert(ip, AddressRole::Pot(pot.name.clone()));
+                }
+            }
+        }
+        result
+    }
+
+    fn insert_bridge(&mut self, bridge: &BridgeConf) {
+        let name = &bridge.name;
+        self.insert(
+            bridge.network.network(),
+            AddressRole::BridgeNetwork(name.clone()),
+        );
+        self.insert(
+            bridge.network.broadcast(),
+            AddressRole::BridgeBroadcast(name.clone()),
+        );
+        self.insert(bridge.gateway, AddressRole::BridgeGateway(name.clone()));
+    }
+
+    /// Reserve all the addresses of a bridge
+    pub fn add_bridge(&mut self, bridge: &BridgeConf) {
+        self.insert_bridge(bridge);
+        self.ranges.push((
+            bridge.network,
+            AddressRole::BridgeAllocated(bridge.name.clone()),
+        ));
+    }
+
+    /// Reserve an address, replacing its previous role
+    pub fn insert(&mut self, ip: IpAddr, role: AddressRole) {
+        self.addresses.insert(ip, role);
+    }
+
+    /// Add the reservations inside the network; the addresses already used keep their role
+    pub fn add_reservations(&mut self, reservations: &BTreeMap<IpAddr, String>) {
+        for (ip, description) in reservations {
+            if self.network.contains(ip) && !self.is_used(ip) {
+                self.addresses
+                    .entry(*ip)
+                    .or_insert_with(|| AddressRole::Reserved(description.clone()));
+            }
+        }
+    }
+
+    /// Forbid a network, like a VPN one or one used by the host
+    pub fn add_forbidden(&mut self, network: IpNet) {
+        self.forbidden.push(network);
+    }
+
+    pub fn get_forbidden(&self) -> &[IpNet] {
+        &self.forbidden
+    }
+
+    /// The role of an address, if it's already used
+    pub fn get_owner(&self, ip: &IpAddr) -> Option<&AddressRole> {
+        self.addresses.get(ip).or_else(|| {
+            self.ranges
+                .iter()
+                .find(|(network, _)| network.contains(ip))
+                .map(|(_, role)| role)
+        })
+    }
+
+    pub fn is_used(&self, ip: &IpAddr) -> bool {
+        self.get_owner(ip).is_some()
+    }
+
+    /// The used addresses, sorted
+    pub fn iter(&self) -> impl Iterator<Item = (&IpAddr, &AddressRole)> {
+        self.addresses.iter()
+    }
+
+    /// The networks whose addresses are all used
+    pub fn iter_ranges(&self) -> impl Iterator<Item = (&IpNet, &AddressRole)> {
+        self.ranges.iter().map(|(network, role)| (network, role))
+    }
+
+    /// The networks that can't provide any address: the forbidden and the used ones
+    fn get_blocked_networks(&self) -> impl Iterator<Item = &IpNet> {
+        self.forbidden
+            .iter()
+            .chain(self.ranges.iter().map(|(network, _)| network))
+    }
+
+    /// Check if a new pot can use the address
+    pub fn validate(&self, ip: &IpAddr) -> std::result::Result<(), IpamError> {
+        if let Some(role) = self.get_owner(ip) {
+            return Err(IpamError::AlreadyInUse(*ip, role.clone()));
+        }
+        if !self.network.contains(ip) {
+            return Err(IpamError::OutsideNetwork(*ip, self.network));
+        }
+        if let Some(network) = self.forbidden.iter().find(|x| x.contains(ip)) {
+            return Err(IpamError::ForbiddenNetwork(*ip, *network));
+        }
+        Ok(())
+    }
+
+    /// The free host address closest to ip, the lower one if two are equally close
+    ///
+    /// If ip is outside the network, the search starts from the closest host of the network
+    pub fn get_nearest_free(&self, ip: &IpAddr) -> Option<IpAddr> {
+        let mut hosts = self.network.hosts();
+        let first = ip_to_u128(&hosts.next()?);
+        let last = hosts.next_back().as_ref().map_or(first, ip_to_u128);
+        if ip.is_ipv4() != self.network.addr().is_ipv4() {
+            return None;
+        }
+        let start = ip_to_u128(ip).clamp(first, last);
+        let is_free = |x: &IpAddr| !self.is_used(x);
+        // the forbidden networks and the used ones are skipped as a whole
+        let mut lower = Some(start);
+        while let Some(candidate) = lower.filter(|x| *x >= first) {
+            let ip = self.u128_to_ip(candidate);
+            match self.get_blocked_networks().find(|f| f.contains(&ip)) {
+                Some(f) => lower = ip_to_u128(&f.network()).checked_sub(1),
+                None if is_free(&ip) => break,
+                None => lower = candidate.checked_sub(1),
+            }
+        }
+        let mut upper = Some(start);
+        while let Some(candidate) = upper.filter(|x| *x <= last) {
+            let ip = self.u128_to_ip(candidate);
+            match self.get_blocked_networks().find(|f| f.contains(&ip)) {
+                Some(f) => upper = ip_to_u128(&f.broadcast()).checked_add(1),
+                None if is_free(&ip) => break,
+                None => upper = candidate.checked_add(1),
+            }
+        }
+        let lower = lower.filter(|x| *x >= first);
+        let upper = upper.filter(|x| *x <= last);
+        let nearest = match (lower, upper) {
+            (Some(l), Some(u)) if start - l <= u - start => l,
+            (_, Some(u)) => u,
+            (l, None) => l?,
+        };
+        Some(self.u128_to_ip(nearest))
+    }
+
+    fn u128_to_ip(&self, x: u128) -> IpAddr {
+        match self.network {
+            IpNet::V4(_) => IpAddr::V4((x as u32).into()),
+            IpNet::V6(_) => IpAddr::V6(x.into()),
+        }
+    }
+
+    /// The subnet has no used address and it doesn't overlap the forbidden or used networks
+    pub fn is_subnet_usable(&self, subnet: &IpNet) -> bool {
+        self.addresses
+            .range(subnet.network()..=subnet.broadcast())
+            .next()
+            .is_none()
+            && !self
+                .get_blocked_networks()
+                .any(|f| is_overlapping(f, subnet))
+    }
+
+    /// Split the network in the largest possible free blocks, sorted by address
+    pub fn get_free_blocks(&self) -> Vec<IpNet> {
+        let mut result = Vec::new();
+        let mut stack = vec![self.network.trunc()];
+        while let Some(net) = stack.pop() {
+            if self.is_subnet_usable(&net) {
+                result.push(net);
+            } else if self.get_blocked_networks().any(|f| f.contains(&net)) {
+                continue;
+            } else if net.prefix_len() < net.max_prefix_len() {
+                if let Ok(halves) = net.subnets(net.prefix_len() + 1) {
+                    // push the upper half first, to visit the lower half first
+                    let halves: Vec<IpNet> = halves.collect();
+                    stack.extend(halves.into_iter().rev());
+                }
+            }
+        }
+        result
+    }
+
+    /// A free subnet, big enough for host_number hosts
+    pub fn new_net(&self, host_number: u16, placement: Placement) -> Option<IpNet> {
+        let prefix_length = get_prefix_length(host_number, &self.network.addr())?;
+        select_subnet(&self.get_free_blocks(), prefix_length, placement)
+    }
+}
+
+/// All the pots, except the one being reconfigured, that has to exist
+fn get_other_pots(conf: &PotSystemConfig, for_pot: Option<&str>) -> Result<Vec<PotConf>> {
+    let mut result = get_pot_conf_list(conf.clone());
+    if let Some(pot_name) = for_pot {
+        if !get_pot_list(conf).iter().any(|x| x == pot_name) {
+            return Err(IpamError::PotNotFound(pot_name.to_string()).into());
+        }
+        result.retain(|x| x.name != pot_name);
+    }
+    Ok(result)
+}
+
+fn ip_to_u128(ip: &IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(u32::from(*ip)),
+        IpAddr::V6(ip) => u128::from(*ip),
+    }
+}
+
+/// Parse the manual reservations, one per line: the address, followed by a description
+///
+/// A line without a valid address is an error, reported with its line number
+pub fn parse_reservations(
+    content: &str,
+) -> std::result::Result<BTreeMap<IpAddr, String>, IpamError> {
+    let mut result = BTreeMap::new();
+    for (index, line) in content.lines().map(str::trim).enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (ip, description) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let ip = ip
+            .parse()
+            .map_err(|_| IpamError::InvalidReservation(index + 1, line.to_string()))?;
+        result.insert(ip, description.trim().to_string());
+    }
+    Ok(result)
+}
+
+/// Read the manual reservations, stored in the potnet directory of the pot root
+pub fn get_reservations(conf: &PotSystemConfig) -> Result<BTreeMap<IpAddr, String>> {
+    let path = Path::new(&conf.fs_root).join("potnet").join("reservations");
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(parse_reservations(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn is_overlapping(a: &IpNet, b: &IpNet) -> bool {
+    a.contains(&b.network()) || b.contains(&a.network())
+}
+
+/// The number of host bits of a network with at least host_number hosts
+pub fn get_network_size(host_number: u16) -> Option<u8> {
+    if host_number == 0 {
+        return None;
+    }
+    let mut max_hosts = 4u16;
+    let mut result = 2;
+    loop {
+        if host_number <= max_hosts - 2 {
+            break;
+        }
+        max_hosts <<= 1;
+        result += 1;
+    }
+    Some(result)
+}
+
+pub fn get_prefix_length(host_number: u16, ip_addr: &IpAddr) -> Option<u8> {
+    get_network_size(host_number).map(|network_size| match ip_addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    } - network_size)
+}
+
+/// Select the subnet with the given prefix length among the free blocks
+pub fn select_subnet(
+    free_blocks: &[IpNet],
+    prefix_length: u8,
+    placement: Placement,
+) -> Option<IpNet> {
+    let mut candidates = free_blocks
+        .iter()
+        .filter(|b| b.prefix_len() <= prefix_length);
+    let block = match placement {
+        Placement::Lowest => candidates.next(),
+        Placement::Highest => candidates.next_back(),
+        // the smallest block, the lowest one if more blocks have the same size
+        Placement::BestFit => candidates.rev().max_by_key(|b| b.prefix_len()),
+    }?;
+    let mut subnets = block.subnets(prefix_length).ok()?;
+    match placement {
+        Placement::Highest => subnets.last(),
+        _ => subnets.next(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn test_conf() -> PotSystemConfig {
+        PotSystemConfig {
+            network: "10.192.0.0/24".parse().unwrap(),
+            gateway: "10.192.0.1".parse().unwrap(),
+            vpn_networks: vec![
+                "10.192.0.128/25".parse().unwrap(),
+                "10.8.0.0/24".parse().unwrap(),
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn test_space() -> AddressSpace {
+        let conf = PotSystemConfig {
+            vpn_networks: Vec::new(),
+            ..test_conf()
+        };
+        let pots = vec![PotConf {
+            name: "pot".to_string(),
+            ip_addr: Some("10.192.0.20".parse().unwrap()),
+            network_type: NetType::PublicBridge,
+        }];
+        AddressSpace::with_pots(&conf, &[], &pots)
+    }
+
+    #[test]
+    fn get_network_size_000() {
+        let uut = get_network_size(2);
+        assert_eq!(uut, Some(2));
+    }
+    #[test]
+    fn get_network_size_001() {
+        let uut = get_network_size(5);
+        assert_eq!(uut, Some(3));
+    }
+    #[test]
+    fn get_network_size_002() {
+        let uut = get_network_size(7);
+        assert_eq!(uut, Some(4));
+    }
+
+    #[test]
+    fn get_prefix_length_000() {
+        let ip_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let uut = get_prefix_length(2, &ip_addr);
+        assert_eq!(uut, Some(30));
+    }
+    #[test]
+    fn get_prefix_length_001() {
+        let ip_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let uut = get_prefix_length(5, &ip_addr);
+        assert_eq!(uut, Some(29));
+    }
+    #[test]
+    fn get_prefix_length_002() {
+        let ip_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let uut = get_prefix_length(9, &ip_addr);
+        assert_eq!(uut, Some(28));
+    }
+    #[test]
+    fn get_prefix_length_010() {
+        let ip_addr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
+        let uut = get_prefix_length(2, &ip_addr);
+        assert_eq!(uut, Some(126));
+    }
+    #[test]
+    fn get_prefix_length_011() {
+        let ip_addr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
+        let uut = get_prefix_length(5, &ip_addr);
+        assert_eq!(uut, Some(125));
+    }
+    #[test]
+    fn get_prefix_length_012() {
+        let ip_addr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
+        let uut = get_prefix_length(9, &ip_addr);
+        assert_eq!(uut, Some(124));
+    }
+
+    #[test]
+    fn with_pots_001() {
+        let conf = test_conf();
+        let bridges = vec![BridgeConf {
+            name: "test-bridge".to_string(),
+            network: "10.192.0.16/29".parse().unwrap(),
+            gateway: "10.192.0.17".parse().unwrap(),
+        }];
+        let pots = vec![
+            PotConf {
+                name: "web1".to_string(),
+                ip_addr: Some("10.192.0.3".parse().unwrap()),
+                network_type: NetType::PublicBridge,
+            },
+            PotConf {
+                name: "db1".to_string(),
+                ip_addr: Some("10.192.0.18".parse().unwrap()),
+                network_type: NetType::PrivateBridge,
+            },
+            PotConf {
+                name: "host".to_string(),
+                ip_addr: None,
+                network_type: NetType::Inherit,
+            },
+        ];
+        let uut = AddressSpace::with_pots(&conf, &bridges, &pots);
+        // network, broadcast, gateway, web1, db1 and the bridge network, broadcast and gateway
+        assert_eq!(uut.iter().count(), 8);
+        assert_eq!(uut.iter_ranges().count(), 1);
+        let get = |ip: &str| uut.get_owner(&ip.parse().unwrap()).cloned();
+        assert_eq!(get("10.192.0.1"), Some(AddressRole::Gateway));
+        assert_eq!(
+            get("10.192.0.3"),
+            Some(AddressRole::Pot("web1".to_string()))
+        );
+        assert_eq!(
+            get("10.192.0.16"),
+            Some(AddressRole::BridgeNetwork("test-bridge".to_string()))
+        );
+        assert_eq!(
+            get("10.192.0.18"),
+            Some(AddressRole::Pot("db1".to_string()))
+        );
+        assert_eq!(
+            get("10.192.0.19"),
+            Some(AddressRole::BridgeAllocated("test-bridge".to_string()))
+        );
+        assert_eq!(get("10.192.0.4"), None);
+        let uut = AddressSpace::with_bridge_pots(&conf, &bridges[0], &pots);
+        assert_eq!(uut.iter().count(), 4);
+        assert_eq!(
+            uut.get_owner(&"10.192.0.18".parse().unwrap()),
+            Some(&AddressRole::Pot("db1".to_string()))
+        );
+        assert_eq!(
+            uut.get_owner(&"10.192.0.17".parse().unwrap())
+                .unwrap()
+                .to_string(),
+            "test-bridge bridge - gateway"
+        );
+    }
+
+    #[test]
+    fn with_pots_002() {
+        let conf = PotSystemConfig {
+            network: "fdf1:186e:49e6::/48".parse().unwrap(),
+            gateway: "fdf1:186e:49e6::1".parse().unwrap(),
+            ..Default::default()
+        };
+        let bridges = vec![BridgeConf {
+            name: "v6-bridge".to_string(),
+            network: "fdf1:186e:49e6::/64".parse().unwrap(),
+            gateway: "fdf1:186e:49e6::2".parse().unwrap(),
+        }];
+        let uut = AddressSpace::with_pots(&conf, &bridges, &[]);
+        let ip: IpAddr = "fdf1:186e:49e6::ffff".parse().unwrap();
+        assert_eq!(
+            uut.validate(&ip),
+            Err(IpamError::AlreadyInUse(
+                ip,
+                AddressRole::BridgeAllocated("v6-bridge".to_string())
+            ))
+        );
+        assert_eq!(
+            uut.get_nearest_free(&ip),
+            Some("fdf1:186e:49e6:1::".parse().unwrap())
+        );
+        assert!(!uut.is_subnet_usable(&"fdf1:186e:49e6::100/120".parse().unwrap()));
+        assert_eq!(
+            uut.get_free_blocks().first(),
+            Some(&"fdf1:186e:49e6:1::/64".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn validate_001() {
+        let conf = test_conf();
+        let uut = AddressSpace::with_pots(&conf, &[], &[]);
+        assert_eq!(uut.validate(&"10.192.0.10".parse().unwrap()), Ok(()));
+        assert_eq!(
+            uut.validate(&"10.192.0.1".parse().unwrap()),
+            Err(IpamError::AlreadyInUse(
+                "10.192.0.1".parse().unwrap(),
+                AddressRole::Gateway
+            ))
+        );
+        assert_eq!(
+            uut.validate(&"10.193.0.1".parse().unwrap()),
+            Err(IpamError::OutsideNetwork(
+                "10.193.0.1".parse().unwrap(),
+                conf.network
+            ))
+        );
+        assert_eq!(
+            uut.validate(&"10.192.0.130".parse().unwrap()),
+            Err(IpamError::ForbiddenNetwork(
+                "10.192.0.130".parse().unwrap(),
+                "10.192.0.128/25".parse().unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_reservations_001() {
+        let uut = parse_reservations(
+            "# manual reservations\n10.192.0.5 printer\n\n10.192.0.6\t old  router \n",
+        )
+        .unwrap();
+        assert_eq!(uut.len(), 2);
+        assert_eq!(
+            uut.get(&"10.192.0.5".parse().unwrap()),
+            Some(&"printer".to_string())
+        );
+        assert_eq!(
+            uut.get(&"10.192.0.6".parse().unwrap()),
+            Some(&"old  router".to_string())
+        );
+        let mut space = test_space();
+        space.add_reservations(&uut);
+        space.add_reservations(
+            &parse_reservations("10.192.0.20 pot\n10.193.0.1 outside\n").unwrap(),
+        );
+        assert_eq!(
+            space.get_owner(&"10.192.0.5".parse().unwrap()),
+            Some(&AddressRole::Reserved("printer".to_string()))
+        );
+        assert_eq!(
+            space.get_owner(&"10.192.0.20".parse().unwrap()),
+            Some(&AddressRole::Pot("pot".to_string()))
+        );
+        assert!(!space.is_used(&"10.193.0.1".parse().unwrap()));
+        assert_eq!(
+            parse_reservations("10.192.0.5 printer\n\nfoo bar\n"),
+            Err(IpamError::InvalidReservation(3, "foo bar".to_string()))
+        );
+    }
+
+    #[test]
+    fn get_nearest_free_001() {
+        let mut uut = test_space();
+        let get = |uut: &AddressSpace, ip: &str| {
+            uut.get_nearest_free(&ip.parse().unwrap())
+                .map(|x| x.to_string())
+        };
+        assert_eq!(get(&uut, "10.192.0.20"), Some("10.192.0.19".to_string()));
+        assert_eq!(get(&uut, "10.192.0.1"), Some("10.192.0.2".to_string()));
+        assert_eq!(get(&uut, "10.192.0.7"), Some("10.192.0.7".to_string()));
+        // outside the network
+        assert_eq!(get(&uut, "10.193.0.1"), Some("10.192.0.254".to_string()));
+        assert_eq!(get(&uut, "fdf1::1"), None);
+        uut.add_forbidden("10.192.0.0/25".parse().unwrap());
+        assert_eq!(get(&uut, "10.192.0.20"), Some("10.192.0.128".to_string()));
+        uut.add_forbidden("10.192.0.128/25".parse().unwrap());
+        assert_eq!(get(&uut, "10.192.0.20"), None);
+    }
+
+    #[test]
+    fn from_system_for_001() {
+        let dir = std::env::temp_dir().join(format!("ipam-test-{}", std::process::id()));
+        let write = |path: &str, content: &str| {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        write(
+            "jails/web1/conf/pot.conf",
+            "network_type=public-bridge\nip=10.192.0.3\n",
+        );
+        write(
+            "jails/db1/conf/pot.conf",
+            "network_type=private-bridge\nip=10.192.0.18\n",
+        );
+        write(
+            "bridges/test-bridge",
+            "name=test-bridge\nnet=10.192.0.16/29\ngateway=10.192.0.17\n",
+        );
+        write(
+            "jails/dns/conf/pot.conf",
+            "network_type=public-bridge\nip=10.192.0.2\n",
+        );
+        write("potnet/reservations", "10.192.0.5 printer\n");
+        let conf = PotSystemConfig {
+            fs_root: dir.to_str().unwrap().to_string(),
+            dns: Some(crate::PotDnsConfig {
+                pot_name: "dns".to_string(),
+                ip: "10.192.0.2".parse().unwrap(),
+            }),
+            ..test_conf()
+        };
+        let web1: IpAddr = "10.192.0.3".parse().unwrap();
+        let db1: IpAddr = "10.192.0.18".parse().unwrap();
+        let uut = AddressSpace::from_system(&conf).unwrap();
+        assert!(uut.validate(&web1).is_err());
+        assert_eq!(
+            uut.get_owner(&"10.192.0.5".parse().unwrap()),
+            Some(&AddressRole::Reserved("printer".to_string()))
+        );
+        let uut = AddressSpace::from_system_for(&conf, Some("web1")).unwrap();
+        assert_eq!(uut.validate(&web1), Ok(()));
+        // the address of db1 is still inside the bridge
+        let uut = AddressSpace::from_system_for(&conf, Some("db1")).unwrap();
+        assert_eq!(
+            uut.get_owner(&db1),
+            Some(&AddressRole::BridgeAllocated("test-bridge".to_string()))
+        );
+        let uut = AddressSpace::from_bridge_for(&conf, "test-bridge", Some("db1")).unwrap();
+        assert_eq!(uut.validate(&db1), Ok(()));
+        let uut = AddressSpace::from_bridge(&conf, "test-bridge").unwrap();
+        assert!(uut.validate(&db1).is_err());
+        assert!(AddressSpace::from_bridge(&conf, "other").is_err());
+        let dns: IpAddr = "10.192.0.2".parse().unwrap();
+        let uut = AddressSpace::from_system(&conf).unwrap();
+        assert!(uut.validate(&dns).is_err());
+        let uut = AddressSpace::from_system_for(&conf, Some("dns")).unwrap();
+        assert_eq!(uut.validate(&dns), Ok(()));
+        assert!(AddressSpace::from_system_for(&conf, Some("ghost")).is_err());
+        assert!(AddressSpace::from_bridge_for(&conf, "test-bridge", Some("ghost")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn get_free_blocks_001() {
+        let uut = test_space().get_free_blocks();
+        let expected: Vec<IpNet> = [
+            "10.192.0.2/31",
+            "10.192.0.4/30",
+            "10.192.0.8/29",
+            "10.192.0.16/30",
+            "10.192.0.21/32",
+            "10.192.0.22/31",
+            "10.192.0.24/29",
+            "10.192.0.32/27",
+            "10.192.0.64/26",
+            "10.192.0.128/26",
+            "10.192.0.192/27",
+            "10.192.0.224/28",
+            "10.192.0.240/29",
+            "10.192.0.248/30",
+            "10.192.0.252/31",
+            "10.192.0.254/32",
+        ]
+        .iter()
+        .map(|x| x.parse().unwrap())
+        .collect();
+        assert_eq!(uut, expected);
+    }
+
+    #[test]
+    fn get_free_blocks_002() {
+        let uut = AddressSpace::new("fdf1:186e:49e6:76d8::/64".parse().unwrap());
+        assert_eq!(
+            uut.get_free_blocks(),
+            vec!["fdf1:186e:49e6:76d8::/64".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn get_free_blocks_003() {
+        let mut uut = AddressSpace::new("10.192.0.0/24".parse().unwrap());
+        uut.add_forbidden("10.192.0.64/26".parse().unwrap());
+        uut.add_forbidden("10.192.0.128/25".parse().unwrap());
+        assert_eq!(
+            uut.get_free_blocks(),
+            vec!["10.192.0.0/26".parse().unwrap()]
+        );
+        let free_blocks = uut.get_free_blocks();
+        assert_eq!(
+            select_subnet(&free_blocks, 28, Placement::Highest),
+            Some("10.192.0.48/28".parse().unwrap())
+        );
+        uut.add_forbidden("10.0.0.0/8".parse().unwrap());
+        assert!(uut.get_free_blocks().is_empty());
+        assert_eq!(uut.new_net(6, Placement::Lowest), None);
+    }
+
+    #[test]
+    fn select_subnet_001() {
+        let free_blocks = test_space().get_free_blocks();
+        let uut = select_subnet(&free_blocks, 29, Placement::Lowest);
+        assert_eq!(uut, Some("10.192.0.8/29".parse().unwrap()));
+        let uut = select_subnet(&free_blocks, 29, Placement::Highest);
+        assert_eq!(uut, Some("10.192.0.240/29".parse().unwrap()));
+        let uut = select_subnet(&free_blocks, 28, Placement::BestFit);
+        assert_eq!(uut, Some("10.192.0.224/28".parse().unwrap()));
+        let uut = select_subnet(&free_blocks, 30, Placement::BestFit);
+        assert_eq!(uut, Some("10.192.0.4/30".parse().unwrap()));
+        let uut = select_subnet(&free_blocks, 25, Placement::BestFit);
+        assert_eq!(uut, None);
+    }
+
+    #[test]
+    fn new_net_001() {
+        let uut = test_space();
+        assert_eq!(
+            uut.new_net(5, Placement::Lowest),
+            Some("10.192.0.8/29".parse().unwrap())
+        );
+        assert_eq!(uut.new_net(0, Placement::Lowest), None);
+    }
+}
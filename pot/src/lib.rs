@@ -1,6 +1,12 @@
 pub mod bridge;
+pub mod command;
+pub mod cpuset;
 pub mod error;
+pub mod host;
+pub mod ipam;
+pub mod rctl;
 mod system;
+pub mod topology;
 pub(crate) mod util;
 
 use ipnet::IpNet;
@@ -31,6 +37,148 @@ pub struct PotSystemConfig {
     pub gateway: IpAddr,
     pub ext_if: String,
     pub dns: Option<PotDnsConfig>,
+    /// The cache of the images and of the flavours (POT_CACHE)
+    pub cache: Option<String>,
+    /// The temporary directory (POT_TMP)
+    pub tmp: Option<String>,
+    /// The suffix of the temporary files (POT_MKTEMP_SUFFIX)
+    pub mktemp_suffix: Option<String>,
+    /// The group allowed to run pot (POT_GROUP)
+    pub group: Option<String>,
+    /// The network stack used by pot (POT_NETWORK_STACK)
+    pub network_stack: Option<NetworkStack>,
+    /// Each vnet pot has its own bridge (POT_ISOLATE_VNET)
+    pub isolate_vnet: Option<bool>,
+    /// The address used on the external interface (POT_EXTIF_ADDR)
+    pub extif_addr: Option<IpAddr>,
+    /// The interface of the VPN (POT_VPN_EXTIF)
+    pub vpn_extif: Option<String>,
+    /// The networks reachable via the VPN (POT_VPN_NETWORKS)
+    pub vpn_networks: Vec<IpNet>,
+    /// The syslog facility used by pot (POT_LOG_FACILITY)
+    pub log_facility: Option<String>,
+    pub provenance: ConfProvenance,
+}
+
+/// The keys of the pot system configuration
+pub const POT_SYSTEM_KEYS: &[&str] = &[
+    "POT_ZFS_ROOT",
+    "POT_FS_ROOT",
+    "POT_EXTIF",
+    "POT_NETWORK",
+    "POT_NETMASK",
+    "POT_GATEWAY",
+    "POT_DNS_NAME",
+    "POT_DNS_IP",
+    "POT_CACHE",
+    "POT_TMP",
+    "POT_MKTEMP_SUFFIX",
+    "POT_GROUP",
+    "POT_NETWORK_STACK",
+    "POT_ISOLATE_VNET",
+    "POT_EXTIF_ADDR",
+    "POT_VPN_EXTIF",
+    "POT_VPN_NETWORKS",
+    "POT_LOG_FACILITY",
+];
+
+/// The network stack used by pot (POT_NETWORK_STACK)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkStack {
+    Ipv4,
+    Ipv6,
+    DualStack,
+}
+
+impl FromStr for NetworkStack {
+    type Err = error::PotError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ipv4" => Ok(NetworkStack::Ipv4),
+            "ipv6" => Ok(NetworkStack::Ipv6),
+            "dual" => Ok(NetworkStack::DualStack),
+            _ => Err(error::PotError::ConfValueError(s.to_string())),
+        }
+    }
+}
+
+/// Where a setting of the system configuration comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfSource {
+    DefaultFile,
+    OverrideFile,
+    Environment,
+    CommandLine,
+}
+
+impl std::fmt::Display for ConfSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfSource::DefaultFile => write!(f, "pot.default.conf"),
+            ConfSource::OverrideFile => write!(f, "pot.conf"),
+            ConfSource::Environment => write!(f, "environment"),
+            ConfSource::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfValue {
+    pub value: String,
+    pub source: ConfSource,
+}
+
+/// A value found in a source, but not used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscardedValue {
+    pub key: String,
+    pub value: String,
+    pub source: ConfSource,
+    pub reason: String,
+}
+
+/// The origin of the effective settings, and the values discarded on the way
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfProvenance {
+    pub values: std::collections::BTreeMap<String, ConfValue>,
+    pub discarded: Vec<DiscardedValue>,
+}
+
+impl ConfProvenance {
+    pub(crate) fn record(&mut self, key: &str, value: &str, source: ConfSource, valid: bool) {
+        if !valid {
+            self.discarded.push(DiscardedValue {
+                key: key.to_string(),
+                value: value.to_string(),
+                source,
+                reason: "invalid value".to_string(),
+            });
+            return;
+        }
+        let new_value = ConfValue {
+            value: value.to_string(),
+            source,
+        };
+        if let Some(old_value) = self.values.insert(key.to_string(), new_value) {
+            self.discarded.push(DiscardedValue {
+                key: key.to_string(),
+                value: old_value.value,
+                source: old_value.source,
+                reason: format!("overridden by {}", source),
+            });
+        }
+    }
+
+    pub(crate) fn discard(&mut self, key: &str, reason: String) {
+        if let Some(old_value) = self.values.remove(key) {
+            self.discarded.push(DiscardedValue {
+                key: key.to_string(),
+                value: old_value.value,
+                source: old_value.source,
+                reason,
+            });
+        }
+    }
 }
 
 impl Default for PotSystemConfig {
@@ -44,14 +192,32 @@ impl Default for PotSystemConfig {
             gateway: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
             ext_if: String::default(),
             dns: None,
+            cache: None,
+            tmp: None,
+            mktemp_suffix: None,
+            group: None,
+            network_stack: None,
+            isolate_vnet: None,
+            extif_addr: None,
+            vpn_extif: None,
+            vpn_networks: Vec::new(),
+            log_facility: None,
+            provenance: ConfProvenance::default(),
         }
     }
 }
 
 impl PotSystemConfig {
     pub fn from_system() -> Result<Self> {
-        let psc = system::PartialSystemConf::new();
-        PotSystemConfig::try_from(psc)
+        PotSystemConfig::from_system_with(&[])
+    }
+
+    /// Read the system configuration, with the overrides provided by the user
+    pub fn from_system_with(overrides: &[(String, String)]) -> Result<Self> {
+        let (psc, provenance) = system::PartialSystemConf::with_provenance(overrides);
+        let mut result = PotSystemConfig::try_from(psc)?;
+        result.provenance = provenance;
+        Ok(result)
     }
 }
 
@@ -71,6 +237,17 @@ impl TryFrom<system::PartialSystemConf> for PotSystemConfig {
                   Some(ip) => Some(PotDnsConfig{pot_name: psc.dns_name.unwrap(), ip}),
                   None => None
                 },
+                cache: psc.cache,
+                tmp: psc.tmp,
+                mktemp_suffix: psc.mktemp_suffix,
+                group: psc.group,
+                network_stack: psc.network_stack,
+                isolate_vnet: psc.isolate_vnet,
+                extif_addr: psc.extif_addr,
+                vpn_extif: psc.vpn_extif,
+                vpn_networks: psc.vpn_networks.unwrap_or_default(),
+                log_facility: psc.log_facility,
+                provenance: ConfProvenance::default(),
             })
         } else {
             Err(error::PotError::IncompleteSystemConf)
@@ -101,6 +278,61 @@ pub struct PotConfVerbatim {
     pub network_type: Option<String>,
 }
 
+/// The resource limits declared in the pot configuration (pot set-rss)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PotRss {
+    pub cpus: Option<u32>,
+    pub memory: Option<String>,
+}
+
+impl FromStr for PotRss {
+    type Err = error::PotError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        use crate::util::get_value;
+        let mut result = PotRss::default();
+        for linestr in s.lines().map(str::trim).filter(|x| !x.starts_with('#')) {
+            if linestr.starts_with("pot.rss.cpus=") {
+                result.cpus = get_value(linestr);
+            }
+            if linestr.starts_with("pot.rss.memory=") {
+                result.memory = get_value(linestr);
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// The addresses of an alias pot, not provided by get_pot_conf_list
+///
+/// The ip setting lists the addresses, each one optionally prefixed by its interface (em0|)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PotAliases {
+    pub addresses: Vec<IpAddr>,
+}
+
+impl FromStr for PotAliases {
+    type Err = error::PotError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut is_alias = false;
+        let mut ip = None;
+        for linestr in s.lines().map(str::trim).filter(|x| !x.starts_with('#')) {
+            match linestr.split_once('=') {
+                Some(("network_type", value)) => is_alias = value.trim_matches('"') == "alias",
+                Some(("ip", value)) => ip = Some(value.trim_matches('"').to_string()),
+                _ => (),
+            }
+        }
+        let mut result = PotAliases::default();
+        if let (true, Some(ip)) = (is_alias, ip) {
+            result.addresses = ip
+                .split_whitespace()
+                .filter_map(|x| x.rsplit('|').next()?.parse().ok())
+                .collect();
+        }
+        Ok(result)
+    }
+}
+
 impl Default for PotConf {
     fn default() -> PotConf {
         PotConf {
@@ -111,6 +343,16 @@ impl Default for PotConf {
     }
 }
 
+/// The origin of each setting of the system configuration, even if it's incomplete
+pub fn get_conf_provenance(overrides: &[(String, String)]) -> ConfProvenance {
+    system::PartialSystemConf::with_provenance(overrides).1
+}
+
+/// The directory containing the pot configuration files (PREFIX/etc/pot)
+pub fn get_conf_dir() -> Result<PathBuf> {
+    system::get_conf_dir()
+}
+
 fn get_pot_path_list(conf: &PotSystemConfig) -> Vec<PathBuf> {
     let mut result = Vec::new();
     let fsroot = conf.fs_root.clone();
@@ -163,6 +405,27 @@ pub fn get_running_pot_list(conf: &PotSystemConfig) -> Vec<String> {
     result
 }
 
+/// Read the resource limits declared in the configuration of the pot
+pub fn get_pot_rss(conf: &PotSystemConfig, pot_name: &str) -> Result<PotRss> {
+    let pot_conf = PathBuf::from(&conf.fs_root)
+        .join("jails")
+        .join(pot_name)
+        .join("conf")
+        .join("pot.conf");
+    let conf_str = std::fs::read_to_string(pot_conf)?;
+    PotRss::from_str(&conf_str)
+}
+
+pub fn get_pot_aliases(conf: &PotSystemConfig, pot_name: &str) -> Result<PotAliases> {
+    let pot_conf = PathBuf::from(&conf.fs_root)
+        .join("jails")
+        .join(pot_name)
+        .join("conf")
+        .join("pot.conf");
+    let conf_str = std::fs::read_to_string(pot_conf)?;
+    PotAliases::from_str(&conf_str)
+}
+
 pub fn get_pot_conf_list(conf: PotSystemConfig) -> Vec<PotConf> {
     let mut v: Vec<PotConf> = Vec::new();
 
@@ -252,3 +515,43 @@ pub fn get_pot_conf_list(conf: PotSystemConfig) -> Vec<PotConf> {
     }
     v
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pot_rss_fromstr_001() {
+        let uut = PotRss::from_str("");
+        assert!(uut.is_ok());
+        assert_eq!(uut.unwrap(), PotRss::default());
+    }
+
+    #[test]
+    fn pot_rss_fromstr_002() {
+        let uut = PotRss::from_str(
+            "ip=10.192.0.3\nnetwork_type=public-bridge\npot.rss.cpus=2\npot.rss.memory=1G",
+        );
+        assert!(uut.is_ok());
+        let uut = uut.unwrap();
+        assert_eq!(uut.cpus, Some(2));
+        assert_eq!(uut.memory, Some("1G".to_string()));
+    }
+
+    #[test]
+    fn pot_rss_fromstr_003() {
+        let uut = PotRss::from_str("# pot.rss.cpus=2\npot.rss.cpus=two");
+        assert!(uut.is_ok());
+        assert_eq!(uut.unwrap().cpus, None);
+    }
+
+    #[test]
+    fn pot_aliases_fromstr_001() {
+        let uut = PotAliases::from_str("network_type=alias\nip=10.1.2.3 em0|fdf1::3 em1|foo\n");
+        assert!(uut.is_ok());
+        let expected: Vec<IpAddr> = vec!["10.1.2.3".parse().unwrap(), "fdf1::3".parse().unwrap()];
+        assert_eq!(uut.unwrap().addresses, expected);
+        let uut = PotAliases::from_str("network_type=public-bridge\nip=10.192.0.3\n");
+        assert_eq!(uut.unwrap(), PotAliases::default());
+    }
+}
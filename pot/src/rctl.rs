@@ -0,0 +1,157 @@
+use crate::command::CommandRunner;
+use crate::error::PotError;
+use crate::Result;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// The resource usage of a subject, as reported by rctl -u
+pub type ResourceUsage = BTreeMap<String, u64>;
+
+/// Parse the output of rctl -u, made of resource=value lines
+pub fn parse_usage(output: &str) -> Result<ResourceUsage> {
+    let mut result = ResourceUsage::new();
+    for line in output.lines().map(str::trim).filter(|x| !x.is_empty()) {
+        let (resource, value) = line
+            .split_once('=')
+            .ok_or_else(|| PotError::RctlError(line.to_string()))?;
+        let value = value
+            .parse()
+            .map_err(|_| PotError::RctlError(line.to_string()))?;
+        result.insert(resource.to_string(), value);
+    }
+    Ok(result)
+}
+
+/// The current resource usage of a jail
+pub fn get_jail_usage(runner: &dyn CommandRunner, jail_name: &str) -> Result<ResourceUsage> {
+    let output = runner.run("/usr/bin/rctl", &["-u", &format!("jail:{}", jail_name)])?;
+    parse_usage(&output)
+}
+
+/// A resource limit rule, in the rctl format subject:subject-id:resource:action=amount/per
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RctlRule {
+    pub subject: String,
+    pub subject_id: String,
+    pub resource: String,
+    pub action: String,
+    pub amount: Option<u64>,
+    pub per: Option<String>,
+}
+
+impl FromStr for RctlRule {
+    type Err = PotError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        let (rule, amount) = match s.split_once('=') {
+            Some((rule, amount)) => (rule, Some(amount)),
+            None => (s, None),
+        };
+        let fields: Vec<&str> = rule.split(':').collect();
+        if fields.len() != 4 || fields.iter().any(|x| x.is_empty()) {
+            return Err(PotError::RctlError(s.to_string()));
+        }
+        let (amount, per) = match amount.map(|x| x.split_once('/')) {
+            None => (None, None),
+            Some(None) => (amount, None),
+            Some(Some((amount, per))) => (Some(amount), Some(per.to_string())),
+        };
+        let amount = match amount {
+            Some(amount) => Some(
+                amount
+                    .parse()
+                    .map_err(|_| PotError::RctlError(s.to_string()))?,
+            ),
+            None => None,
+        };
+        Ok(RctlRule {
+            subject: fields[0].to_string(),
+            subject_id: fields[1].to_string(),
+            resource: fields[2].to_string(),
+            action: fields[3].to_string(),
+            amount,
+            per,
+        })
+    }
+}
+
+/// Parse a list of rules, one per line, as printed by rctl
+pub fn parse_rules(output: &str) -> Result<Vec<RctlRule>> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(RctlRule::from_str)
+        .collect()
+}
+
+/// The rules whose subject is the jail
+pub fn get_jail_rules(runner: &dyn CommandRunner, jail_name: &str) -> Result<Vec<RctlRule>> {
+    let output = runner.run("/usr/bin/rctl", &[&format!("jail:{}:", jail_name)])?;
+    parse_rules(&output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_usage_001() {
+        let uut = parse_usage("");
+        assert!(uut.is_ok());
+        assert!(uut.unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_usage_002() {
+        let output = "cputime=12\ndatasize=1138688\nmemoryuse=24653824\npcpu=37\nopenfiles=196\n";
+        let uut = parse_usage(output);
+        assert!(uut.is_ok());
+        let uut = uut.unwrap();
+        assert_eq!(uut.len(), 5);
+        assert_eq!(uut.get("pcpu"), Some(&37));
+        assert_eq!(uut.get("memoryuse"), Some(&24653824));
+    }
+
+    #[test]
+    fn parse_usage_003() {
+        assert!(parse_usage("pcpu").is_err());
+        assert!(parse_usage("memoryuse=24M").is_err());
+    }
+
+    #[test]
+    fn rctl_rule_fromstr_001() {
+        let uut = RctlRule::from_str("jail:web1:memoryuse:deny=1073741824");
+        assert!(uut.is_ok());
+        let uut = uut.unwrap();
+        assert_eq!(uut.subject, "jail");
+        assert_eq!(uut.subject_id, "web1");
+        assert_eq!(uut.resource, "memoryuse");
+        assert_eq!(uut.action, "deny");
+        assert_eq!(uut.amount, Some(1073741824));
+        assert_eq!(uut.per, None);
+    }
+
+    #[test]
+    fn rctl_rule_fromstr_002() {
+        let uut = RctlRule::from_str("user:1001:pcpu:devctl=50/jail");
+        assert!(uut.is_ok());
+        let uut = uut.unwrap();
+        assert_eq!(uut.amount, Some(50));
+        assert_eq!(uut.per, Some("jail".to_string()));
+        assert!(RctlRule::from_str("jail:web1:memoryuse").is_err());
+        assert!(RctlRule::from_str("jail:web1:memoryuse:deny=1G").is_err());
+        assert!(RctlRule::from_str("jail::memoryuse:deny=10").is_err());
+    }
+
+    #[test]
+    fn parse_rules_001() {
+        let output = "jail:web1:memoryuse:deny=1073741824\njail:web1:pcpu:deny=200\n\n";
+        let uut = parse_rules(output);
+        assert!(uut.is_ok());
+        let uut = uut.unwrap();
+        assert_eq!(uut.len(), 2);
+        assert_eq!(uut[1].resource, "pcpu");
+        assert!(parse_rules("jail:web1:pcpu").is_err());
+    }
+}
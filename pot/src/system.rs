@@ -1,6 +1,8 @@
 use crate::error::PotError;
-use crate::Result;
+use crate::util::parse_assignments;
+use crate::{ConfProvenance, ConfSource, NetworkStack, Result, POT_SYSTEM_KEYS};
 use ipnet::IpNet;
+use std::collections::HashMap;
 use std::default::Default;
 use std::net::IpAddr;
 use std::path::PathBuf;
@@ -17,32 +19,127 @@ pub(crate) struct PartialSystemConf {
     pub(crate) ext_if: Option<String>,
     pub(crate) dns_name: Option<String>,
     pub(crate) dns_ip: Option<IpAddr>,
+    pub(crate) cache: Option<String>,
+    pub(crate) tmp: Option<String>,
+    pub(crate) mktemp_suffix: Option<String>,
+    pub(crate) group: Option<String>,
+    pub(crate) network_stack: Option<NetworkStack>,
+    pub(crate) isolate_vnet: Option<bool>,
+    pub(crate) extif_addr: Option<IpAddr>,
+    pub(crate) vpn_extif: Option<String>,
+    pub(crate) vpn_networks: Option<Vec<IpNet>>,
+    pub(crate) log_facility: Option<String>,
 }
 
 impl PartialSystemConf {
-    pub fn new() -> PartialSystemConf {
-        let s = match get_conf_default() {
-            Ok(s) => s,
-            Err(_) => return PartialSystemConf::default(),
-        };
-
-        let mut dconf = PartialSystemConf::from_str(&s).ok().unwrap_or_default();
-        let s = match get_conf() {
-            Ok(s) => s,
-            Err(_) => return dconf,
-        };
-        let pconf = PartialSystemConf::from_str(&s).ok().unwrap_or_default();
-        let pconf_has_dns_ip = pconf.dns_ip.is_some();
-        dconf.merge(pconf);
+    /// Read the configuration from all the sources, in order of precedence: the environment,
+    /// pot.default.conf, pot.conf and the overrides given by the user
+    ///
+    /// As for pot, the files are authoritative: the environment only provides the settings
+    /// they don't assign (and the values of their ${VAR} expansions)
+    pub(crate) fn with_provenance(
+        overrides: &[(String, String)],
+    ) -> (PartialSystemConf, ConfProvenance) {
+        let mut provenance = ConfProvenance::default();
+        let mut result = PartialSystemConf::default();
+        let env: Vec<(String, String)> = std::env::vars()
+            .filter(|(name, _value)| POT_SYSTEM_KEYS.contains(&name.as_str()))
+            .collect();
+        result.merge(PartialSystemConf::from_assignments(
+            env,
+            ConfSource::Environment,
+            &mut provenance,
+        ));
+        // pot.conf can refer to the variables of pot.default.conf
+        let mut vars = HashMap::new();
+        if let Ok(s) = get_conf_default() {
+            let dconf =
+                PartialSystemConf::parse(&s, &mut vars, ConfSource::DefaultFile, &mut provenance);
+            result.merge(dconf);
+        }
+        if let Ok(s) = get_conf() {
+            let pconf =
+                PartialSystemConf::parse(&s, &mut vars, ConfSource::OverrideFile, &mut provenance);
+            result.merge(pconf);
+        }
+        result.merge(PartialSystemConf::from_assignments(
+            overrides.to_vec(),
+            ConfSource::CommandLine,
+            &mut provenance,
+        ));
         // remove dns_ip if it came from default config and is not inside pot network
-        if !pconf_has_dns_ip {
-            if let Some(dns_ip) = &dconf.dns_ip {
-                if !dconf.network.unwrap().contains(dns_ip) {
-                    dconf.dns_ip = None;
+        if let (Some(dns_ip), Some(network)) = (&result.dns_ip, &result.network) {
+            if !network.contains(dns_ip) {
+                let from_default = provenance
+                    .values
+                    .get("POT_DNS_IP")
+                    .is_some_and(|x| x.source == ConfSource::DefaultFile);
+                if from_default {
+                    result.dns_ip = None;
+                    provenance.discard(
+                        "POT_DNS_IP",
+                        "the default DNS IP is outside the pot network".to_string(),
+                    );
                 }
             }
         }
-        dconf
+        (result, provenance)
+    }
+
+    /// Parse a configuration file, expanding the variables with vars and the environment
+    ///
+    /// vars is updated with the assignments of the file, so that the next file can use them
+    fn parse(
+        s: &str,
+        vars: &mut HashMap<String, String>,
+        source: ConfSource,
+        provenance: &mut ConfProvenance,
+    ) -> PartialSystemConf {
+        PartialSystemConf::from_assignments(parse_assignments(s, vars), source, provenance)
+    }
+
+    fn from_assignments(
+        assignments: Vec<(String, String)>,
+        source: ConfSource,
+        provenance: &mut ConfProvenance,
+    ) -> PartialSystemConf {
+        fn set<T: FromStr>(field: &mut Option<T>, value: &str) -> bool {
+            *field = value.parse().ok();
+            field.is_some()
+        }
+        let mut result = PartialSystemConf::default();
+        for (name, value) in assignments {
+            let valid = match name.as_str() {
+                "POT_ZFS_ROOT" => set(&mut result.zfs_root, &value),
+                "POT_FS_ROOT" => set(&mut result.fs_root, &value),
+                "POT_EXTIF" => set(&mut result.ext_if, &value),
+                "POT_DNS_NAME" => set(&mut result.dns_name, &value),
+                "POT_NETWORK" => set(&mut result.network, &value),
+                "POT_NETMASK" => set(&mut result.netmask, &value),
+                "POT_GATEWAY" => set(&mut result.gateway, &value),
+                "POT_DNS_IP" => set(&mut result.dns_ip, &value),
+                "POT_CACHE" => set(&mut result.cache, &value),
+                "POT_TMP" => set(&mut result.tmp, &value),
+                "POT_MKTEMP_SUFFIX" => set(&mut result.mktemp_suffix, &value),
+                "POT_GROUP" => set(&mut result.group, &value),
+                "POT_NETWORK_STACK" => set(&mut result.network_stack, &value),
+                "POT_ISOLATE_VNET" => set(&mut result.isolate_vnet, &value),
+                "POT_EXTIF_ADDR" => set(&mut result.extif_addr, &value),
+                "POT_VPN_EXTIF" => set(&mut result.vpn_extif, &value),
+                "POT_VPN_NETWORKS" => {
+                    result.vpn_networks = value
+                        .split_whitespace()
+                        .map(str::parse)
+                        .collect::<std::result::Result<_, _>>()
+                        .ok();
+                    result.vpn_networks.is_some()
+                }
+                "POT_LOG_FACILITY" => set(&mut result.log_facility, &value),
+                _ => continue,
+            };
+            provenance.record(&name, &value, source, valid);
+        }
+        result
     }
 
     pub fn is_valid(&self) -> bool {
@@ -57,11 +154,11 @@ impl PartialSystemConf {
     }
 
     fn merge(&mut self, rhs: PartialSystemConf) {
-        if rhs.zfs_root.is_some() {
-            self.zfs_root = Some(rhs.zfs_root.unwrap());
+        if let Some(zfs_root) = rhs.zfs_root {
+            self.zfs_root = Some(zfs_root);
         }
-        if rhs.fs_root.is_some() {
-            self.fs_root = Some(rhs.fs_root.unwrap());
+        if let Some(fs_root) = rhs.fs_root {
+            self.fs_root = Some(fs_root);
         }
         self.network = match rhs.network {
             Some(s) => Some(s),
@@ -75,64 +172,50 @@ impl PartialSystemConf {
             Some(s) => Some(s),
             None => self.gateway,
         };
-        if rhs.ext_if.is_some() {
-            self.ext_if = Some(rhs.ext_if.unwrap());
+        if let Some(ext_if) = rhs.ext_if {
+            self.ext_if = Some(ext_if);
         }
-        if rhs.dns_name.is_some() {
-            self.dns_name = Some(rhs.dns_name.unwrap());
+        if let Some(dns_name) = rhs.dns_name {
+            self.dns_name = Some(dns_name);
         }
         self.dns_ip = match rhs.dns_ip {
             Some(s) => Some(s),
             None => self.dns_ip,
         };
+        self.cache = rhs.cache.or(self.cache.take());
+        self.tmp = rhs.tmp.or(self.tmp.take());
+        self.mktemp_suffix = rhs.mktemp_suffix.or(self.mktemp_suffix.take());
+        self.group = rhs.group.or(self.group.take());
+        self.network_stack = rhs.network_stack.or(self.network_stack);
+        self.isolate_vnet = rhs.isolate_vnet.or(self.isolate_vnet);
+        self.extif_addr = rhs.extif_addr.or(self.extif_addr);
+        self.vpn_extif = rhs.vpn_extif.or(self.vpn_extif.take());
+        self.vpn_networks = rhs.vpn_networks.or(self.vpn_networks.take());
+        self.log_facility = rhs.log_facility.or(self.log_facility.take());
     }
 }
 
 impl FromStr for PartialSystemConf {
     type Err = PotError;
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        use crate::util::get_value;
-        let mut default = PartialSystemConf::default();
-        let lines: Vec<String> = s
-            .to_string()
-            .lines()
-            .map(|x| x.trim().to_string())
-            .filter(|x| !x.starts_with('#'))
-            .collect();
-        for linestr in &lines {
-            if linestr.starts_with("POT_ZFS_ROOT=") {
-                default.zfs_root = get_value(linestr);
-            }
-            if linestr.starts_with("POT_FS_ROOT=") {
-                default.fs_root = get_value(linestr);
-            }
-            if linestr.starts_with("POT_EXTIF=") {
-                default.ext_if = get_value(linestr);
-            }
-            if linestr.starts_with("POT_DNS_NAME=") {
-                default.dns_name = get_value(linestr);
-            }
-            if linestr.starts_with("POT_NETWORK=") {
-                default.network = get_value(linestr);
-            }
-            if linestr.starts_with("POT_NETMASK=") {
-                default.netmask = get_value(linestr);
-            }
-            if linestr.starts_with("POT_GATEWAY=") {
-                default.gateway = get_value(linestr);
-            }
-            if linestr.starts_with("POT_DNS_IP=") {
-                default.dns_ip = get_value(linestr);
-            }
-        }
-        Ok(default)
+        Ok(PartialSystemConf::parse(
+            s,
+            &mut HashMap::new(),
+            ConfSource::DefaultFile,
+            &mut ConfProvenance::default(),
+        ))
     }
 }
 
+pub(crate) fn get_conf_dir() -> Result<PathBuf> {
+    let mut pot_conf_dir = get_pot_prefix()?;
+    pot_conf_dir.push("etc");
+    pot_conf_dir.push("pot");
+    Ok(pot_conf_dir)
+}
+
 pub(crate) fn get_conf_default() -> Result<String> {
-    let mut pot_conf = get_pot_prefix()?;
-    pot_conf.push("etc");
-    pot_conf.push("pot");
+    let mut pot_conf = get_conf_dir()?;
     pot_conf.push("pot.default.conf");
 
     let result = std::fs::read_to_string(pot_conf)?;
@@ -140,9 +223,7 @@ pub(crate) fn get_conf_default() -> Result<String> {
 }
 
 pub(crate) fn get_conf() -> Result<String> {
-    let mut pot_conf = get_pot_prefix()?;
-    pot_conf.push("etc");
-    pot_conf.push("pot");
+    let mut pot_conf = get_conf_dir()?;
     pot_conf.push("pot.conf");
 
     let result = std::fs::read_to_string(pot_conf)?;
@@ -169,9 +250,9 @@ fn get_pot_prefix() -> Result<PathBuf> {
     Ok(pot_prefix.to_path_buf())
 }
 #[cfg(test)]
-
 mod tests {
     use super::*;
+    use crate::ConfValue;
 
     #[test]
     fn partial_system_conf_default() {
@@ -270,7 +351,7 @@ mod tests {
         assert!(!uut.is_valid());
         assert_ne!(uut, PartialSystemConf::default());
         assert!(uut.dns_name.is_some());
-        assert_ne!(uut.dns_name.unwrap(), "FOO_DNS".to_string());
+        assert_eq!(uut.dns_name.unwrap(), "FOO_DNS".to_string());
     }
 
     #[test]
@@ -443,4 +524,119 @@ mod tests {
             .unwrap()
         );
     }
+
+    #[test]
+    fn partial_system_conf_fromstr_013() {
+        let uut = PartialSystemConf::from_str(
+            "export POT_FS_ROOT='/opt/pot'\nPOT_ZFS_ROOT=\"zroot/pot\" # root dataset\nPOT_DNS_NAME=\"dns \\\"pot\\\"\"",
+        );
+        assert!(uut.is_ok());
+        let uut = uut.unwrap();
+        assert_eq!(uut.fs_root, Some("/opt/pot".to_string()));
+        assert_eq!(uut.zfs_root, Some("zroot/pot".to_string()));
+        assert_eq!(uut.dns_name, Some("dns \"pot\"".to_string()));
+    }
+
+    #[test]
+    fn partial_system_conf_parse_001() {
+        let mut vars = HashMap::new();
+        let mut provenance = ConfProvenance::default();
+        let dconf = PartialSystemConf::parse(
+            "POT_ZFS_ROOT=zroot/pot\nPOT_FS_ROOT=/opt/pot\nPOT_EXTIF=em0",
+            &mut vars,
+            ConfSource::DefaultFile,
+            &mut provenance,
+        );
+        assert_eq!(dconf.fs_root, Some("/opt/pot".to_string()));
+        let pconf = PartialSystemConf::parse(
+            "POT_ZFS_ROOT=${POT_ZFS_ROOT}/jails\nPOT_FS_ROOT=\"$POT_FS_ROOT/jails\"",
+            &mut vars,
+            ConfSource::OverrideFile,
+            &mut provenance,
+        );
+        assert_eq!(pconf.zfs_root, Some("zroot/pot/jails".to_string()));
+        assert_eq!(pconf.fs_root, Some("/opt/pot/jails".to_string()));
+        assert_eq!(pconf.ext_if, None);
+    }
+
+    #[test]
+    fn partial_system_conf_provenance_001() {
+        let mut provenance = ConfProvenance::default();
+        PartialSystemConf::parse(
+            "POT_ZFS_ROOT=zroot/pot\nPOT_NETWORK=10.192.0.0/10\nPOT_GATEWAY=10.192.0.1\nFOO=bar",
+            &mut HashMap::new(),
+            ConfSource::DefaultFile,
+            &mut provenance,
+        );
+        PartialSystemConf::parse(
+            "POT_NETWORK=10.0.0.0\nPOT_GATEWAY=10.192.0.254",
+            &mut HashMap::new(),
+            ConfSource::OverrideFile,
+            &mut provenance,
+        );
+        assert_eq!(provenance.values.len(), 3);
+        assert_eq!(
+            provenance.values.get("POT_ZFS_ROOT"),
+            Some(&ConfValue {
+                value: "zroot/pot".to_string(),
+                source: ConfSource::DefaultFile
+            })
+        );
+        assert_eq!(
+            provenance.values.get("POT_NETWORK").unwrap().source,
+            ConfSource::DefaultFile
+        );
+        assert_eq!(
+            provenance.values.get("POT_GATEWAY").unwrap().source,
+            ConfSource::OverrideFile
+        );
+        assert_eq!(provenance.discarded.len(), 2);
+        assert_eq!(provenance.discarded[0].key, "POT_NETWORK");
+        assert_eq!(provenance.discarded[0].value, "10.0.0.0");
+        assert_eq!(provenance.discarded[0].reason, "invalid value");
+        assert_eq!(provenance.discarded[1].key, "POT_GATEWAY");
+        assert_eq!(provenance.discarded[1].value, "10.192.0.1");
+        assert_eq!(provenance.discarded[1].reason, "overridden by pot.conf");
+    }
+
+    #[test]
+    fn partial_system_conf_fromstr_014() {
+        let uut = PartialSystemConf::from_str(
+            "POT_CACHE=/var/cache/pot\nPOT_TMP=/tmp\nPOT_MKTEMP_SUFFIX=.XXXXXXXX\nPOT_GROUP=pot\n
+            POT_NETWORK_STACK=dual\nPOT_ISOLATE_VNET=true\nPOT_EXTIF_ADDR=192.168.1.10\n
+            POT_VPN_EXTIF=tun0\nPOT_VPN_NETWORKS=\"10.8.0.0/24 192.168.100.0/24\"\nPOT_LOG_FACILITY=local2",
+        );
+        assert!(uut.is_ok());
+        let uut = uut.unwrap();
+        assert!(!uut.is_valid());
+        assert_eq!(uut.cache, Some("/var/cache/pot".to_string()));
+        assert_eq!(uut.tmp, Some("/tmp".to_string()));
+        assert_eq!(uut.mktemp_suffix, Some(".XXXXXXXX".to_string()));
+        assert_eq!(uut.group, Some("pot".to_string()));
+        assert_eq!(uut.network_stack, Some(NetworkStack::DualStack));
+        assert_eq!(uut.isolate_vnet, Some(true));
+        assert_eq!(
+            uut.extif_addr,
+            Some("192.168.1.10".parse::<IpAddr>().unwrap())
+        );
+        assert_eq!(uut.vpn_extif, Some("tun0".to_string()));
+        assert_eq!(
+            uut.vpn_networks,
+            Some(vec![
+                "10.8.0.0/24".parse::<IpNet>().unwrap(),
+                "192.168.100.0/24".parse::<IpNet>().unwrap()
+            ])
+        );
+        assert_eq!(uut.log_facility, Some("local2".to_string()));
+    }
+
+    #[test]
+    fn partial_system_conf_fromstr_015() {
+        let uut = PartialSystemConf::from_str(
+            "POT_NETWORK_STACK=ipv5\nPOT_ISOLATE_VNET=maybe\nPOT_VPN_NETWORKS=\"10.8.0.0/24 foo\"",
+        );
+        assert!(uut.is_ok());
+        let uut = uut.unwrap();
+        assert_eq!(uut, PartialSystemConf::default());
+    }
 }
@@ -0,0 +1,256 @@
+use crate::error::PotError;
+use std::str::FromStr;
+
+/// A group of CPUs, as described by the FreeBSD scheduler topology
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CpuGroup {
+    pub level: u32,
+    /// The level of the cache shared by the CPUs of the group (0 means no shared cache)
+    pub cache_level: u32,
+    pub cpus: Vec<u32>,
+    pub flags: Vec<String>,
+    pub children: Vec<CpuGroup>,
+}
+
+impl CpuGroup {
+    /// The group is made of the hardware threads of a single core
+    pub fn is_smt(&self) -> bool {
+        self.flags.iter().any(|f| f == "SMT" || f == "THREAD")
+    }
+
+    fn groups<'a>(&'a self, result: &mut Vec<&'a CpuGroup>) {
+        result.push(self);
+        for child in &self.children {
+            child.groups(result);
+        }
+    }
+
+    fn shared_depth(&self, a: u32, b: u32, depth: u32) -> Option<u32> {
+        if !self.cpus.contains(&a) || !self.cpus.contains(&b) {
+            return None;
+        }
+        Some(
+            self.children
+                .iter()
+                .find_map(|child| child.shared_depth(a, b, depth + 1))
+                .unwrap_or(depth),
+        )
+    }
+}
+
+/// The CPU topology, parsed from the kern.sched.topology_spec sysctl
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuTopology {
+    pub root: CpuGroup,
+}
+
+impl CpuTopology {
+    /// All the groups, the root first
+    pub fn groups(&self) -> Vec<&CpuGroup> {
+        let mut result = Vec::new();
+        self.root.groups(&mut result);
+        result
+    }
+
+    /// The physical packages (sockets)
+    ///
+    /// A root without a shared cache is a multi-package system, its children are the packages
+    pub fn packages(&self) -> Vec<&CpuGroup> {
+        if self.root.cache_level == 0 && !self.root.children.is_empty() {
+            self.root.children.iter().collect()
+        } else {
+            vec![&self.root]
+        }
+    }
+
+    /// The physical cores, each one with its hardware threads
+    ///
+    /// Without SMT groups, every CPU is a core
+    pub fn cores(&self) -> Vec<Vec<u32>> {
+        let smt_groups: Vec<Vec<u32>> = self
+            .groups()
+            .into_iter()
+            .filter(|g| g.is_smt())
+            .map(|g| g.cpus.clone())
+            .collect();
+        let mut result = smt_groups.clone();
+        for cpu in &self.root.cpus {
+            if !smt_groups.iter().any(|g| g.contains(cpu)) {
+                result.push(vec![*cpu]);
+            }
+        }
+        result.sort();
+        result
+    }
+
+    /// The groups of CPUs sharing a cache
+    pub fn cache_groups(&self) -> Vec<&CpuGroup> {
+        self.groups()
+            .into_iter()
+            .filter(|g| g.cache_level != 0)
+            .collect()
+    }
+
+    /// The depth of the smallest group containing both CPUs (0 is the root)
+    pub fn shared_depth(&self, a: u32, b: u32) -> Option<u32> {
+        self.root.shared_depth(a, b, 0)
+    }
+}
+
+/// The value of the attribute in a tag, like `level="1"`
+fn get_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{}=\"", name);
+    let start = tag.find(&pattern)? + pattern.len();
+    let end = tag[start..].find('"')? + start;
+    Some(&tag[start..end])
+}
+
+impl FromStr for CpuTopology {
+    type Err = PotError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut stack: Vec<CpuGroup> = Vec::new();
+        let mut root = None;
+        let mut rest = s;
+        while let Some(start) = rest.find('<') {
+            let end = rest[start..]
+                .find('>')
+                .ok_or_else(|| PotError::TopologyError("unterminated tag".to_string()))?
+                + start;
+            let tag = &rest[start + 1..end];
+            rest = &rest[end + 1..];
+            // the text between this tag and the next one
+            let text = &rest[..rest.find('<').unwrap_or(rest.len())];
+            let name = tag.split_whitespace().next().unwrap_or("");
+            match name {
+                "group" => {
+                    let level = get_attribute(tag, "level").and_then(|x| x.parse().ok());
+                    let cache_level =
+                        get_attribute(tag, "cache-level").and_then(|x| x.parse().ok());
+                    stack.push(CpuGroup {
+                        level: level.unwrap_or_default(),
+                        cache_level: cache_level.unwrap_or_default(),
+                        ..Default::default()
+                    });
+                }
+                "/group" => {
+                    let group = stack.pop().ok_or_else(|| {
+                        PotError::TopologyError("unexpected </group>".to_string())
+                    })?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(group),
+                        None => root = Some(group),
+                    }
+                }
+                "cpu" => {
+                    let group = stack.last_mut().ok_or_else(|| {
+                        PotError::TopologyError("<cpu> outside of a group".to_string())
+                    })?;
+                    for cpu in text.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+                        group.cpus.push(cpu.parse().map_err(|_| {
+                            PotError::TopologyError(format!("invalid cpu {}", cpu))
+                        })?);
+                    }
+                }
+                "flag" => {
+                    if let (Some(group), Some(flag)) =
+                        (stack.last_mut(), get_attribute(tag, "name"))
+                    {
+                        group.flags.push(flag.to_string());
+                    }
+                }
+                _ => (),
+            }
+        }
+        if !stack.is_empty() {
+            return Err(PotError::TopologyError("unterminated group".to_string()));
+        }
+        root.map(|root| CpuTopology { root })
+            .ok_or_else(|| PotError::TopologyError("no group found".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUAL_SOCKET: &str = r#"<groups>
+ <group level="1" cache-level="0">
+  <cpu count="8" mask="ff,0,0,0">0, 1, 2, 3, 4, 5, 6, 7</cpu>
+  <children>
+   <group level="2" cache-level="3">
+    <cpu count="4" mask="f,0,0,0">0, 1, 2, 3</cpu>
+    <children>
+     <group level="3" cache-level="2">
+      <cpu count="2" mask="3,0,0,0">0, 1</cpu>
+      <flags><flag name="THREAD">THREAD group</flag><flag name="SMT">SMT group</flag></flags>
+     </group>
+     <group level="3" cache-level="2">
+      <cpu count="2" mask="c,0,0,0">2, 3</cpu>
+      <flags><flag name="THREAD">THREAD group</flag><flag name="SMT">SMT group</flag></flags>
+     </group>
+    </children>
+   </group>
+   <group level="2" cache-level="3">
+    <cpu count="4" mask="f0,0,0,0">4, 5, 6, 7</cpu>
+    <children>
+     <group level="3" cache-level="2">
+      <cpu count="2" mask="30,0,0,0">4, 5</cpu>
+      <flags><flag name="THREAD">THREAD group</flag><flag name="SMT">SMT group</flag></flags>
+     </group>
+     <group level="3" cache-level="2">
+      <cpu count="2" mask="c0,0,0,0">6, 7</cpu>
+      <flags><flag name="THREAD">THREAD group</flag><flag name="SMT">SMT group</flag></flags>
+     </group>
+    </children>
+   </group>
+  </children>
+ </group>
+</groups>
+"#;
+
+    const SINGLE_SOCKET: &str = r#"<groups>
+ <group level="1" cache-level="2">
+  <cpu count="4" mask="f,0,0,0">0, 1, 2, 3</cpu>
+ </group>
+</groups>
+"#;
+
+    #[test]
+    fn cpu_topology_fromstr_001() {
+        let uut = CpuTopology::from_str(DUAL_SOCKET);
+        assert!(uut.is_ok());
+        let uut = uut.unwrap();
+        assert_eq!(uut.root.cpus, vec![0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(uut.groups().len(), 7);
+        let packages = uut.packages();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[1].cpus, vec![4, 5, 6, 7]);
+        assert_eq!(
+            uut.cores(),
+            vec![vec![0, 1], vec![2, 3], vec![4, 5], vec![6, 7]]
+        );
+        assert_eq!(uut.cache_groups().len(), 6);
+        assert_eq!(uut.shared_depth(0, 1), Some(2));
+        assert_eq!(uut.shared_depth(0, 2), Some(1));
+        assert_eq!(uut.shared_depth(0, 4), Some(0));
+        assert_eq!(uut.shared_depth(0, 8), None);
+    }
+
+    #[test]
+    fn cpu_topology_fromstr_002() {
+        let uut = CpuTopology::from_str(SINGLE_SOCKET);
+        assert!(uut.is_ok());
+        let uut = uut.unwrap();
+        assert_eq!(uut.packages().len(), 1);
+        assert_eq!(uut.cores(), vec![vec![0], vec![1], vec![2], vec![3]]);
+        assert_eq!(uut.cache_groups().len(), 1);
+    }
+
+    #[test]
+    fn cpu_topology_fromstr_003() {
+        assert!(CpuTopology::from_str("").is_err());
+        assert!(CpuTopology::from_str("<groups><group level=\"1\">").is_err());
+        assert!(CpuTopology::from_str("<groups><cpu>0</cpu></groups>").is_err());
+        assert!(CpuTopology::from_str("<group><cpu>a</cpu></group>").is_err());
+    }
+}
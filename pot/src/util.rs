@@ -1,20 +1,124 @@
+use std::collections::HashMap;
 use std::str::FromStr;
 
 pub(crate) fn get_value<T>(line: &str) -> Option<T>
 where
     T: FromStr,
 {
-    match line.split('=').nth(1) {
-        Some(value) => match value.split(' ').next() {
-            Some(value) => value.parse().ok(),
-            None => None,
-        },
-        None => None,
+    let (_name, value) = parse_assignment(line, &HashMap::new())?;
+    value.parse().ok()
+}
+
+/// Parse the assignments of a configuration file written in a subset of the sh syntax
+///
+/// The variables are expanded with the values assigned by the previous lines (or
+/// already in vars), then with the environment; vars is updated with the new assignments
+pub(crate) fn parse_assignments(
+    s: &str,
+    vars: &mut HashMap<String, String>,
+) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    for line in s.lines() {
+        if let Some((name, value)) = parse_assignment(line, vars) {
+            vars.insert(name.clone(), value.clone());
+            result.push((name, value));
+        }
     }
+    result
 }
 
-#[cfg(test)]
+/// Parse a line like NAME=value, supporting the export prefix, quotes, escapes, comments
+/// and the $NAME or ${NAME} expansion
+pub(crate) fn parse_assignment(
+    line: &str,
+    vars: &HashMap<String, String>,
+) -> Option<(String, String)> {
+    let line = line.trim();
+    let line = line.strip_prefix("export ").map_or(line, str::trim_start);
+    let (name, value) = line.split_once('=')?;
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+    if name.is_empty()
+        || name.starts_with(|c: char| c.is_ascii_digit())
+        || !name.chars().all(is_name_char)
+    {
+        return None;
+    }
+    let mut result = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => break,
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    result.push(c);
+                }
+            }
+            '\'' => {
+                for c in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    result.push(c);
+                }
+            }
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.peek() {
+                            Some(&next) if "$`\"\\".contains(next) => {
+                                result.push(next);
+                                chars.next();
+                            }
+                            _ => result.push(c),
+                        },
+                        '$' => expand_variable(&mut chars, vars, &mut result),
+                        _ => result.push(c),
+                    }
+                }
+            }
+            '$' => expand_variable(&mut chars, vars, &mut result),
+            _ => result.push(c),
+        }
+    }
+    Some((name.to_string(), result))
+}
+
+/// Expand the variable following a $, looking into vars first, then into the environment
+fn expand_variable(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    vars: &HashMap<String, String>,
+    result: &mut String,
+) {
+    let mut name = String::new();
+    if chars.peek() == Some(&'{') {
+        chars.next();
+        for c in chars.by_ref() {
+            if c == '}' {
+                break;
+            }
+            name.push(c);
+        }
+    } else {
+        while let Some(&c) = chars.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+    }
+    if name.is_empty() {
+        result.push('$');
+        return;
+    }
+    match vars.get(&name) {
+        Some(value) => result.push_str(value),
+        None => result.push_str(&std::env::var(&name).unwrap_or_default()),
+    }
+}
 
+#[cfg(test)]
 mod tests {
     use super::*;
 
@@ -36,4 +140,56 @@ mod tests {
         let uut: Option<String> = get_value("key=Catched Ignore");
         assert_eq!(uut, Some("Catched".to_string()));
     }
+
+    #[test]
+    fn test_parse_assignment() {
+        let vars = HashMap::new();
+        let parse = |line| parse_assignment(line, &vars).map(|(_name, value)| value);
+        assert_eq!(parse("key=\"foo bar\""), Some("foo bar".to_string()));
+        assert_eq!(
+            parse("key='foo $bar \\n'"),
+            Some("foo $bar \\n".to_string())
+        );
+        assert_eq!(
+            parse("key=foo\\ bar # comment"),
+            Some("foo bar".to_string())
+        );
+        assert_eq!(
+            parse("key=\"a \\\"b\\\" \\c\""),
+            Some("a \"b\" \\c".to_string())
+        );
+        assert_eq!(parse("key=a=b"), Some("a=b".to_string()));
+        assert_eq!(parse("key="), Some(String::new()));
+        assert_eq!(parse("export key=1"), Some("1".to_string()));
+        assert_eq!(parse("key=1$"), Some("1$".to_string()));
+        assert_eq!(parse("# key=1"), None);
+        assert_eq!(parse("echo foo"), None);
+        assert_eq!(parse("bad key=1"), None);
+        assert_eq!(parse("=1"), None);
+        assert_eq!(
+            parse_assignment("pot.rss.cpus=2", &vars),
+            Some(("pot.rss.cpus".to_string(), "2".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_assignments() {
+        let mut vars = HashMap::new();
+        vars.insert("PREFIX".to_string(), "/usr/local".to_string());
+        let uut = parse_assignments(
+            "# comment\nROOT=/opt/pot\nexport CACHE=${ROOT}/cache\n\nTMP=\"$ROOT/tmp\"\nETC=$PREFIX/etc\nNONE=${POTNET_TEST_UNDEFINED}x",
+            &mut vars,
+        );
+        assert_eq!(
+            uut,
+            vec![
+                ("ROOT".to_string(), "/opt/pot".to_string()),
+                ("CACHE".to_string(), "/opt/pot/cache".to_string()),
+                ("TMP".to_string(), "/opt/pot/tmp".to_string()),
+                ("ETC".to_string(), "/usr/local/etc".to_string()),
+                ("NONE".to_string(), "x".to_string()),
+            ]
+        );
+        assert_eq!(vars.get("CACHE"), Some(&"/opt/pot/cache".to_string()));
+    }
 }
@@ -1,13 +1,21 @@
 #![cfg_attr(test, feature(proc_macro_hygiene))]
+// the mockable attribute generates code swapping raw pointers
+#![cfg_attr(test, allow(clippy::swap_ptr_to_ref))]
 
-use anyhow::{anyhow, Result};
+use anyhow::{bail, Result};
 use itertools::Itertools;
-use log::{info, trace, warn};
+use log::{error, info, trace, warn};
 #[cfg(test)]
 use mocktopus::macros::*;
-use pot_rs::{get_running_pot_list, PotSystemConfig};
-use std::collections::HashMap;
+use pot_rs::command::{CommandRunner, SystemRunner};
+use pot_rs::cpuset::{format_cpulist, parse_cpulist, parse_cpuset_output};
+use pot_rs::rctl::get_jail_usage;
+use pot_rs::topology::{CpuGroup, CpuTopology};
+use pot_rs::{get_conf_dir, get_pot_list, get_pot_rss, get_running_pot_list, PotSystemConfig};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::process::{Command as PCommand, Stdio};
+use std::str::FromStr;
 use structopt::StructOpt;
 use structopt_flags::{LogLevel, QuietVerbose};
 
@@ -28,37 +36,170 @@ enum Command {
     /// Get a cpu allocation for a new jail
     #[structopt(name = "get-cpu")]
     GetCpu(GetCpuOpt),
+    /// Release the CPUs reserved to a pot by get-cpu
+    #[structopt(name = "release-cpu")]
+    ReleaseCpu(ReleaseCpuOpt),
     /// Propose a new allocation layout if needed
     #[structopt(name = "rebalance")]
-    Rebalance,
+    Rebalance(RebalanceOpt),
+    /// Propose a target layout for all the pots, including the ones not running
+    #[structopt(name = "plan")]
+    Plan(PlanOpt),
 }
 
-#[derive(Debug, StructOpt, Copy, Clone)]
+#[derive(Debug, StructOpt, Clone)]
 struct GetCpuOpt {
     /// Amount of CPUs needed by that pot
     #[structopt(short = "n", long = "num", default_value = "1")]
     cpu_amount: u32,
+    /// The name of the pot: the CPUs declared in its configuration win over --num and
+    /// the allocation is reserved until the pot is running or release-cpu is called
+    #[structopt(short = "j", long = "pot-name")]
+    pot_name: Option<String>,
+    /// Provide only CPUs not used by any other pinned pot; with --pot-name, the CPUs stay
+    /// exclusive to that pot, even once it's running, until release-cpu is called
+    #[structopt(short = "x", long = "exclusive")]
+    exclusive: bool,
+    /// How to use the CPU topology: pack (CPUs sharing caches) or spread (different cores)
+    #[structopt(short = "p", long = "policy", default_value = "pack")]
+    policy: Policy,
+    /// How to measure the CPU load: count (pots per CPU) or usage (sampled CPU usage)
+    #[structopt(short = "l", long = "load-model", default_value = "count")]
+    load_model: LoadModel,
+    /// The sampling interval in seconds, used by the usage load model
+    #[structopt(short = "i", long = "interval", default_value = "5")]
+    interval: u64,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+struct ReleaseCpuOpt {
+    /// The name of the pot
+    pot_name: String,
+}
+
+#[derive(Debug, StructOpt, Copy, Clone)]
+struct RebalanceOpt {
+    /// Apply the new allocation layout to the running pots
+    #[structopt(long = "apply")]
+    apply: bool,
+    /// Only print the commands needed to apply the new layout (default, wins over --apply)
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+    /// The accepted difference between the most and the least loaded CPU
+    /// (in pots, default 1, or in percent of a CPU with the usage load model, default 25)
+    #[structopt(short = "t", long = "tolerance")]
+    tolerance: Option<u32>,
+    /// How to use the CPU topology: pack (CPUs sharing caches) or spread (different cores)
+    #[structopt(short = "p", long = "policy", default_value = "pack")]
+    policy: Policy,
+    /// How to measure the CPU load: count (pots per CPU) or usage (sampled CPU usage)
+    #[structopt(short = "l", long = "load-model", default_value = "count")]
+    load_model: LoadModel,
+    /// The sampling interval in seconds, used by the usage load model
+    #[structopt(short = "i", long = "interval", default_value = "5")]
+    interval: u64,
+}
+
+#[derive(Debug, StructOpt, Copy, Clone)]
+struct PlanOpt {
+    /// The accepted difference between the most and the least loaded CPU
+    #[structopt(short = "t", long = "tolerance", default_value = "1")]
+    tolerance: u32,
+    /// How to use the CPU topology: pack (CPUs sharing caches) or spread (different cores)
+    #[structopt(short = "p", long = "policy", default_value = "pack")]
+    policy: Policy,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Policy {
+    Pack,
+    Spread,
+}
+
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "pack" => Ok(Policy::Pack),
+            "spread" => Ok(Policy::Spread),
+            _ => bail!("unknown policy {} (pack or spread)", s),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum LoadModel {
+    Count,
+    Usage,
+}
+
+impl LoadModel {
+    /// The default tolerance: the sampled usage is noisy, a few percent of a CPU are not
+    /// worth moving a pot
+    fn default_tolerance(self) -> u32 {
+        match self {
+            LoadModel::Count => 1,
+            LoadModel::Usage => 25,
+        }
+    }
+}
+
+impl FromStr for LoadModel {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "count" => Ok(LoadModel::Count),
+            "usage" => Ok(LoadModel::Usage),
+            _ => bail!("unknown load model {} (count or usage)", s),
+        }
+    }
 }
 
 type Allocation = Vec<u32>;
 type AllocationRef = [u32];
 
+/// The potcpu configuration, read from potcpu.conf in the pot configuration directory
+///
+/// reserved_cpus=0,1 : CPUs reserved to the host, never provided to pots
+/// exclusive_pots=db1,web1 : pots whose CPUs are never shared with other pots
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct PotcpuConf {
+    reserved_cpus: Allocation,
+    exclusive_pots: Vec<String>,
+}
+
+impl FromStr for PotcpuConf {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut result = PotcpuConf::default();
+        for line in s.lines().map(str::trim).filter(|x| !x.starts_with('#')) {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let values = value
+                .split('#')
+                .next()
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty());
+            match key.trim() {
+                "reserved_cpus" => {
+                    for cpu in values {
+                        result.reserved_cpus.push(cpu.parse()?);
+                    }
+                }
+                "exclusive_pots" => result.exclusive_pots.extend(values.map(str::to_string)),
+                _ => (),
+            }
+        }
+        Ok(result)
+    }
+}
+
 fn allocation_from_utf8(v: &[u8]) -> Result<Allocation> {
     let output_string = std::str::from_utf8(v)?;
-    let first_line = output_string
-        .lines()
-        .next()
-        .ok_or_else(|| anyhow!("cpuset: no stdout"))?;
-    let mask = first_line
-        .split(':')
-        .nth(1)
-        .ok_or_else(|| anyhow!("cpuset: malformed stdout"))?;
-    let result: Allocation = mask
-        .split(',')
-        .map(str::trim)
-        .flat_map(str::parse)
-        .collect();
-    Ok(result)
+    Ok(parse_cpuset_output(output_string)?)
 }
 
 fn allocation_to_string(allocation: &AllocationRef, ncpu: u32) -> String {
@@ -90,6 +231,19 @@ fn get_ncpu() -> Result<u32> {
     Ok(ncpu)
 }
 
+#[cfg_attr(test, mockable)]
+fn get_topology() -> Result<CpuTopology> {
+    let output = PCommand::new("/sbin/sysctl")
+        .arg("-n")
+        .arg("kern.sched.topology_spec")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .output()?;
+    let output_string = std::str::from_utf8(&output.stdout)?;
+    Ok(output_string.parse()?)
+}
+
 #[cfg_attr(test, mockable)]
 fn get_cpusets(conf: &PotSystemConfig) -> Result<HashMap<String, Allocation>> {
     let mut result = HashMap::new();
@@ -112,6 +266,112 @@ fn get_cpusets(conf: &PotSystemConfig) -> Result<HashMap<String, Allocation>> {
     Ok(result)
 }
 
+/// The CPUs provided by get-cpu to a pot
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Reservation {
+    cpus: Allocation,
+    /// Requested with get-cpu --exclusive: the CPUs are not shared, even once the pot runs
+    exclusive: bool,
+}
+
+/// The reservations of the pots that are not running yet, and of the exclusive ones
+type Ledger = BTreeMap<String, Reservation>;
+
+fn get_ledger_dir(conf: &PotSystemConfig) -> PathBuf {
+    Path::new(&conf.fs_root).join("potcpu")
+}
+
+/// An exclusive flock(2) on the ledger, released when dropped or when the process dies
+struct LedgerLock {
+    _file: std::fs::File,
+}
+
+impl LedgerLock {
+    fn acquire(dir: &Path) -> Result<LedgerLock> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join("ledger.lock");
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        for _ in 0..50 {
+            match file.try_lock() {
+                Ok(()) => return Ok(LedgerLock { _file: file }),
+                Err(std::fs::TryLockError::WouldBlock) => {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+                Err(std::fs::TryLockError::Error(e)) => return Err(e.into()),
+            }
+        }
+        bail!("the ledger {} is locked by another potcpu", path.display())
+    }
+}
+
+/// Read the ledger, without the entries that are not reservations anymore
+fn get_reconciled_ledger(conf: &PotSystemConfig, path: &Path) -> Result<Ledger> {
+    let mut ledger = read_ledger(path)?;
+    reconcile_ledger(
+        &mut ledger,
+        &get_pot_list(conf),
+        &get_running_pot_list(conf),
+    );
+    Ok(ledger)
+}
+
+fn read_ledger(path: &Path) -> Result<Ledger> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Ledger::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut result = Ledger::new();
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        if let (Some(pot_name), Some(cpus)) = (fields.next(), fields.next()) {
+            match parse_cpulist(cpus) {
+                Ok(cpus) => {
+                    let exclusive = fields.next() == Some("exclusive");
+                    result.insert(pot_name.to_string(), Reservation { cpus, exclusive });
+                }
+                Err(e) => warn!("ledger: ignoring the entry of pot {}: {}", pot_name, e),
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn write_ledger(path: &Path, ledger: &Ledger) -> Result<()> {
+    let mut result = String::new();
+    for (pot_name, reservation) in ledger {
+        result.push_str(&format!(
+            "{} {}{}\n",
+            pot_name,
+            allocation_to_cpulist(&reservation.cpus),
+            if reservation.exclusive {
+                " exclusive"
+            } else {
+                ""
+            }
+        ));
+    }
+    std::fs::write(path, result)?;
+    Ok(())
+}
+
+/// Remove the stale entries: the running pots are already pinned, unless they are exclusive,
+/// and the pots that don't exist anymore will never start
+fn reconcile_ledger(ledger: &mut Ledger, pots: &[String], running_pots: &[String]) {
+    ledger.retain(|pot_name, reservation| {
+        let stale =
+            !pots.contains(pot_name) || (running_pots.contains(pot_name) && !reservation.exclusive);
+        if stale {
+            info!("ledger: removing the entry of pot {}", pot_name);
+        }
+        !stale
+    });
+}
+
 fn get_potcpuconstraints(
     allocations: &HashMap<String, Allocation>,
 ) -> Result<HashMap<String, u32>> {
@@ -126,18 +386,112 @@ fn get_potcpuconstraints(
     Ok(result)
 }
 
+#[cfg_attr(test, mockable)]
+fn get_potcpu_conf() -> Result<PotcpuConf> {
+    let path = get_conf_dir()?.join("potcpu.conf");
+    match std::fs::read_to_string(path) {
+        Ok(conf_str) => conf_str.parse(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(PotcpuConf::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Add the reserved CPUs of the pots without a cpuset: they count as pinned pots
+fn add_reserved_allocations(allocations: &mut HashMap<String, Allocation>, ledger: &Ledger) {
+    for (pot_name, reservation) in ledger {
+        allocations
+            .entry(pot_name.clone())
+            .or_insert_with(|| reservation.cpus.clone());
+    }
+}
+
+/// A pot is exclusive if potcpu.conf says so or if its CPUs were reserved with --exclusive
+fn is_exclusive(pot_name: &str, potcpu_conf: &PotcpuConf, ledger: &Ledger) -> bool {
+    potcpu_conf
+        .exclusive_pots
+        .iter()
+        .any(|name| name == pot_name)
+        || ledger
+            .get(pot_name)
+            .is_some_and(|reservation| reservation.exclusive)
+}
+
+/// Remove the CPUs that can't be provided to pots: the reserved ones and the ones used by
+/// exclusive pots
+fn remove_unavailable_cpus(
+    cpu_loads: &mut HashMap<u32, u32>,
+    potcpu_conf: &PotcpuConf,
+    ledger: &Ledger,
+    allocations: &HashMap<String, Allocation>,
+    ncpu: u32,
+) {
+    cpu_loads.retain(|cpu, _load| !potcpu_conf.reserved_cpus.contains(cpu));
+    for (pot_name, cpus) in allocations {
+        // an exclusive pot without restrictions is not pinned yet
+        if is_exclusive(pot_name, potcpu_conf, ledger) && (cpus.len() as u32) < ncpu {
+            cpu_loads.retain(|cpu, _load| !cpus.contains(cpu));
+        }
+    }
+}
+
+/// A declaration of 0 CPUs is ignored: it would be an empty cpuset
+fn check_declared_cpus(pot_name: &str, cpus: Option<u32>) -> Option<u32> {
+    match cpus {
+        Some(0) => {
+            warn!("pot {}: pot.rss.cpus=0 ignored", pot_name);
+            None
+        }
+        cpus => cpus,
+    }
+}
+
+#[cfg_attr(test, mockable)]
+fn get_declared_cpus(conf: &PotSystemConfig, pot_name: &str) -> Option<u32> {
+    check_declared_cpus(pot_name, get_pot_rss(conf, pot_name).ok()?.cpus)
+}
+
+/// The CPU constraints of the pots: the CPUs declared in pot.conf win over the current mask
+///
+/// A pot declaring all the CPUs, or more, is constrained to all the CPUs
+fn get_declared_potcpuconstraints(
+    conf: &PotSystemConfig,
+    allocations: &HashMap<String, Allocation>,
+) -> Result<HashMap<String, u32>> {
+    let mut result = get_potcpuconstraints(allocations)?;
+    let ncpu = get_ncpu()?;
+    for pot_name in allocations.keys() {
+        if let Some(cpus) = get_declared_cpus(conf, pot_name) {
+            result.insert(pot_name.to_string(), cpus.min(ncpu));
+        }
+    }
+    Ok(result)
+}
+
 fn show(opt: &Opt, conf: &PotSystemConfig) -> Result<()> {
     let ncpu = get_ncpu()?;
     let pot_cpusets = get_cpusets(conf)?;
-    let pot_constraints = get_potcpuconstraints(&pot_cpusets)?;
+    let pot_constraints = get_declared_potcpuconstraints(conf, &pot_cpusets)?;
     for (pot_name, allocation) in pot_cpusets {
+        let declared = get_declared_cpus(conf, &pot_name);
         let constraint_string = match pot_constraints.iter().find(|(name, _)| *name == &pot_name) {
+            Some(constraint) if declared.is_some() => format!("{} (declared)", constraint.1),
             Some(constraint) => constraint.1.to_string(),
             None => "NA".to_string(),
         };
         println!("pot {}:", pot_name);
         println!("\tCPU requested: {}", constraint_string);
         println!("\tCPU used: {}", allocation_to_string(&allocation, ncpu));
+        if let Some(declared) = declared {
+            if declared.min(ncpu) != allocation.len() as u32 {
+                warn!(
+                    "pot {} declares {} CPUs, but it's using {}",
+                    pot_name,
+                    declared,
+                    allocation.len()
+                );
+                println!("\tCPU mismatch: the mask differs from the declared CPUs");
+            }
+        }
     }
     if opt.verbose.get_level_filter() > log::LevelFilter::Warn {
         let cpu_allocations = get_cpu_allocation(conf)?;
@@ -154,41 +508,580 @@ fn show(opt: &Opt, conf: &PotSystemConfig) -> Result<()> {
 fn get_cpu_allocation(conf: &PotSystemConfig) -> Result<HashMap<u32, u32>> {
     let pot_cpusets = get_cpusets(conf)?;
     let ncpu = get_ncpu()?;
-    let mut result: HashMap<u32, u32> = HashMap::new();
-    for i in 0..ncpu {
-        result.insert(i, 0);
+    Ok(get_cpu_loads(&pot_cpusets, &HashMap::new(), ncpu))
+}
+
+/// The weight of a pot on each of its CPUs, 1 if not known
+fn get_weight(weights: &HashMap<String, u32>, pot_name: &str) -> u32 {
+    weights.get(pot_name).copied().unwrap_or(1)
+}
+
+/// The load of each CPU, as the sum of the weights of the pots using it
+fn get_cpu_loads(
+    allocations: &HashMap<String, Allocation>,
+    weights: &HashMap<String, u32>,
+    ncpu: u32,
+) -> HashMap<u32, u32> {
+    let mut result: HashMap<u32, u32> = (0..ncpu).map(|cpu| (cpu, 0)).collect();
+    for (pot_name, cpus) in allocations {
+        for cpu in cpus {
+            *result.entry(*cpu).or_default() += get_weight(weights, pot_name);
+        }
     }
-    for allocations in pot_cpusets.values() {
-        for cpu_num in allocations {
-            let old_value = result.remove(cpu_num).unwrap();
-            result.insert(*cpu_num, old_value + 1);
+    result
+}
+
+/// Sample the CPU usage (pcpu, in percent of a CPU) of the pots, once per second, returning
+/// the average
+fn sample_pot_usages(
+    runner: &dyn CommandRunner,
+    pot_names: &[&str],
+    interval: u64,
+) -> Result<HashMap<String, u32>> {
+    let samples = interval.max(1);
+    let mut totals: HashMap<String, u64> = HashMap::new();
+    for sample in 0..samples {
+        if sample > 0 {
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+        for pot_name in pot_names {
+            let usage = get_jail_usage(runner, pot_name)?;
+            let pcpu = usage.get("pcpu").copied().unwrap_or_default();
+            *totals.entry(pot_name.to_string()).or_default() += pcpu;
         }
     }
-    Ok(result)
+    Ok(totals
+        .into_iter()
+        .map(|(pot_name, total)| (pot_name, (total / samples) as u32))
+        .collect())
+}
+
+/// The weight of the pots on each of their CPUs: the CPU usage is spread evenly on the
+/// allocated CPUs, and idle pots still weigh 1
+fn get_usage_weights(
+    allocations: &HashMap<String, Allocation>,
+    usages: &HashMap<String, u32>,
+) -> HashMap<String, u32> {
+    allocations
+        .iter()
+        .filter(|(_pot_name, cpus)| !cpus.is_empty())
+        .map(|(pot_name, cpus)| {
+            let usage = usages.get(pot_name).copied().unwrap_or_default();
+            (pot_name.clone(), (usage / cpus.len() as u32).max(1))
+        })
+        .collect()
 }
 
-fn get_cpu(_opt: &Opt, conf: &PotSystemConfig, cpu_amount: u32) -> Result<()> {
+/// The weights of the pots according to the load model (empty with the count model)
+fn get_load_weights(
+    allocations: &HashMap<String, Allocation>,
+    load_model: LoadModel,
+    interval: u64,
+) -> Result<HashMap<String, u32>> {
+    match load_model {
+        LoadModel::Count => Ok(HashMap::new()),
+        LoadModel::Usage => {
+            let pot_names: Vec<&str> = allocations.keys().map(String::as_str).sorted().collect();
+            info!("sampling the CPU usage for {} seconds", interval.max(1));
+            let usages = sample_pot_usages(&SystemRunner, &pot_names, interval)?;
+            Ok(get_usage_weights(allocations, &usages))
+        }
+    }
+}
+
+fn get_available_cpus(group: &CpuGroup, cpu_loads: &HashMap<u32, u32>) -> Allocation {
+    group
+        .cpus
+        .iter()
+        .filter_map(|cpu| cpu_loads.get(cpu).map(|load| (*cpu, *load)))
+        .sorted_by_key(|(cpu, load)| (*load, *cpu))
+        .map(|(cpu, _load)| cpu)
+        .collect()
+}
+
+/// The average load of the available CPUs of the group, in thousandths
+fn get_group_load(group: &CpuGroup, cpu_loads: &HashMap<u32, u32>) -> u32 {
+    let loads: Vec<u32> = group
+        .cpus
+        .iter()
+        .filter_map(|cpu| cpu_loads.get(cpu).copied())
+        .collect();
+    if loads.is_empty() {
+        return u32::MAX;
+    }
+    loads.iter().sum::<u32>() * 1000 / loads.len() as u32
+}
+
+/// Select the CPUs from the smallest group able to host all of them
+fn select_pack(group: &CpuGroup, amount: usize, cpu_loads: &HashMap<u32, u32>) -> Allocation {
+    let available = get_available_cpus(group, cpu_loads);
+    if group.children.is_empty() || amount >= available.len() {
+        return available.into_iter().take(amount).collect();
+    }
+    if let Some(child) = group
+        .children
+        .iter()
+        .map(|child| (child, get_available_cpus(child, cpu_loads).len()))
+        .filter(|(_child, available)| *available >= amount)
+        .min_by_key(|(child, available)| {
+            (
+                *available,
+                get_group_load(child, cpu_loads),
+                child.cpus.first().copied(),
+            )
+        })
+        .map(|(child, _available)| child)
+    {
+        return select_pack(child, amount, cpu_loads);
+    }
+    // no child is big enough, fill the least loaded children first
+    let mut result = Vec::new();
+    for child in group.children.iter().sorted_by_key(|child| {
+        (
+            get_group_load(child, cpu_loads),
+            child.cpus.first().copied(),
+        )
+    }) {
+        let missing = amount - result.len();
+        if missing == 0 {
+            break;
+        }
+        let child_amount = missing.min(get_available_cpus(child, cpu_loads).len());
+        result.extend(select_pack(child, child_amount, cpu_loads));
+    }
+    result
+}
+
+/// Select the CPUs distributing them evenly among the children groups
+fn select_spread(group: &CpuGroup, amount: usize, cpu_loads: &HashMap<u32, u32>) -> Allocation {
+    if group.children.is_empty() {
+        return get_available_cpus(group, cpu_loads)
+            .into_iter()
+            .take(amount)
+            .collect();
+    }
+    let children: Vec<(&CpuGroup, usize)> = group
+        .children
+        .iter()
+        .sorted_by_key(|child| {
+            (
+                get_group_load(child, cpu_loads),
+                child.cpus.first().copied(),
+            )
+        })
+        .map(|child| (child, get_available_cpus(child, cpu_loads).len()))
+        .collect();
+    let mut quotas = vec![0; children.len()];
+    let mut assigned = 0;
+    while assigned < amount {
+        let mut progress = false;
+        for (i, (_child, available)) in children.iter().enumerate() {
+            if assigned < amount && quotas[i] < *available {
+                quotas[i] += 1;
+                assigned += 1;
+                progress = true;
+            }
+        }
+        if !progress {
+            break;
+        }
+    }
+    children
+        .iter()
+        .zip(quotas)
+        .flat_map(|((child, _available), quota)| select_spread(child, quota, cpu_loads))
+        .collect()
+}
+
+/// How CPUs are chosen, following the CPU topology if available
+struct CpuPlacement {
+    topology: Option<CpuTopology>,
+    policy: Policy,
+}
+
+impl CpuPlacement {
+    fn new(policy: Policy) -> CpuPlacement {
+        let topology = match get_topology() {
+            Ok(topology) => Some(topology),
+            Err(e) => {
+                info!("CPU topology not available, ignoring it: {}", e);
+                None
+            }
+        };
+        CpuPlacement { topology, policy }
+    }
+
+    /// Choose the CPUs among the ones with a load
+    fn select(&self, amount: usize, cpu_loads: &HashMap<u32, u32>) -> Allocation {
+        match (&self.topology, self.policy) {
+            (Some(topology), Policy::Pack) => select_pack(&topology.root, amount, cpu_loads),
+            (Some(topology), Policy::Spread) => select_spread(&topology.root, amount, cpu_loads),
+            (None, _) => cpu_loads
+                .iter()
+                .sorted_by_key(|(cpu, load)| (**load, **cpu))
+                .map(|(cpu, _load)| *cpu)
+                .take(amount)
+                .collect(),
+        }
+    }
+
+    /// How unsuitable the CPU is for a pot already using the other CPUs (lower is better)
+    fn affinity(&self, cpus: &AllocationRef, cpu: u32) -> u32 {
+        let Some(topology) = &self.topology else {
+            return 0;
+        };
+        let depth = cpus
+            .iter()
+            .filter_map(|x| topology.shared_depth(*x, cpu))
+            .max()
+            .unwrap_or_default();
+        match self.policy {
+            Policy::Pack => u32::MAX - depth,
+            Policy::Spread => depth,
+        }
+    }
+}
+
+fn get_cpu(_opt: &Opt, conf: &PotSystemConfig, cmd_opt: &GetCpuOpt) -> Result<()> {
     let ncpu = get_ncpu()?;
+    let cpu_amount = match cmd_opt
+        .pot_name
+        .as_ref()
+        .and_then(|pot_name| get_declared_cpus(conf, pot_name))
+    {
+        Some(declared) => {
+            info!("pot.conf declares {} CPUs", declared);
+            declared
+        }
+        None => cmd_opt.cpu_amount,
+    };
+    if cpu_amount == 0 {
+        bail!("at least one CPU has to be requested");
+    }
     if ncpu <= cpu_amount {
         info!("Not enough CPU in the system to provide a meaningful allocation");
         return Ok(());
     }
-    let cpu_allocations = get_cpu_allocation(conf)?;
-    let sorted_cpu_allocations = cpu_allocations
-        .iter()
-        .sorted_by_key(|(cpu, _allocations)| *cpu)
-        .sorted_by_key(|(_cpu, allocations)| *allocations);
-    let mut cpu_string = String::new();
-    for (cpu, _) in sorted_cpu_allocations.take(cpu_amount as usize) {
-        cpu_string.push_str(&cpu.to_string());
-        cpu_string.push(',');
-    }
-    println!("{}", cpu_string.trim_end_matches(','));
+    let ledger_dir = get_ledger_dir(conf);
+    let _lock = LedgerLock::acquire(&ledger_dir)?;
+    let ledger_path = ledger_dir.join("ledger");
+    let mut ledger = get_reconciled_ledger(conf, &ledger_path)?;
+    if let Some(pot_name) = &cmd_opt.pot_name {
+        ledger.remove(pot_name);
+    }
+    let mut pot_cpusets = get_cpusets(conf)?;
+    let weights = get_load_weights(&pot_cpusets, cmd_opt.load_model, cmd_opt.interval)?;
+    add_reserved_allocations(&mut pot_cpusets, &ledger);
+    let mut cpu_allocations = get_cpu_loads(&pot_cpusets, &weights, ncpu);
+    if cmd_opt.exclusive {
+        for cpus in pot_cpusets.values() {
+            if (cpus.len() as u32) < ncpu {
+                cpu_allocations.retain(|cpu, _load| !cpus.contains(cpu));
+            }
+        }
+    }
+    remove_unavailable_cpus(
+        &mut cpu_allocations,
+        &get_potcpu_conf()?,
+        &ledger,
+        &pot_cpusets,
+        ncpu,
+    );
+    if (cpu_allocations.len() as u32) < cpu_amount {
+        bail!("Not enough CPUs available: {} left", cpu_allocations.len());
+    }
+    let placement = CpuPlacement::new(cmd_opt.policy);
+    let cpus = placement.select(cpu_amount as usize, &cpu_allocations);
+    if let Some(pot_name) = &cmd_opt.pot_name {
+        let reservation = Reservation {
+            cpus: cpus.clone(),
+            exclusive: cmd_opt.exclusive,
+        };
+        ledger.insert(pot_name.clone(), reservation);
+    }
+    write_ledger(&ledger_path, &ledger)?;
+    println!("{}", allocation_to_cpulist(&cpus));
     Ok(())
 }
 
-fn rebalance(_opt: &Opt, conf: &PotSystemConfig) -> Result<()> {
-    let cpu_counters = get_cpu_allocation(conf)?;
+fn release_cpu(conf: &PotSystemConfig, cmd_opt: &ReleaseCpuOpt) -> Result<()> {
+    let ledger_dir = get_ledger_dir(conf);
+    let _lock = LedgerLock::acquire(&ledger_dir)?;
+    let ledger_path = ledger_dir.join("ledger");
+    let mut ledger = get_reconciled_ledger(conf, &ledger_path)?;
+    if ledger.remove(&cmd_opt.pot_name).is_none() {
+        info!("pot {} has no reserved CPUs", cmd_opt.pot_name);
+    }
+    write_ledger(&ledger_path, &ledger)
+}
+
+fn allocation_to_cpulist(allocation: &AllocationRef) -> String {
+    format_cpulist(allocation)
+}
+
+/// The largest amount of pot subsets evaluated looking for a smaller rebalance
+const MAX_REBALANCE_SUBSETS: u128 = 10_000;
+
+/// The most loaded and the least loaded CPUs are within the tolerance
+fn is_balanced(loads: &HashMap<u32, u32>, tolerance: u32) -> bool {
+    match (loads.values().max(), loads.values().min()) {
+        (Some(max), Some(min)) => max - min <= tolerance,
+        _ => true,
+    }
+}
+
+/// The number of subsets of size k of a set of size n
+fn get_combinations(n: usize, k: usize) -> u128 {
+    (0..k as u128).fold(1, |result, i| result * (n as u128 - i) / (i + 1))
+}
+
+/// Move CPUs of the movable pots from the most loaded CPUs to the least loaded ones,
+/// until the load difference is within the tolerance
+///
+/// Every move reduces the difference between the two CPUs involved, so it always ends; it
+/// returns the new allocations and the new loads
+fn rebalance_pots(
+    allocations: &HashMap<String, Allocation>,
+    cpu_loads: &HashMap<u32, u32>,
+    weights: &HashMap<String, u32>,
+    tolerance: u32,
+    placement: &CpuPlacement,
+    movable: &[&String],
+) -> (HashMap<String, Allocation>, HashMap<u32, u32>) {
+    let mut new_allocations = allocations.clone();
+    let mut loads = cpu_loads.clone();
+    let mut moved_pots: Vec<String> = Vec::new();
+    while !is_balanced(&loads, tolerance) {
+        // the CPUs out of tolerance, the most loaded first, the lowest one in case of a
+        // tie; if no pot can leave them, raising the least loaded CPUs can still help
+        let min_load = loads.values().min().copied().unwrap_or_default();
+        let sources: Vec<(u32, u32)> = loads
+            .iter()
+            .map(|(cpu, load)| (*cpu, *load))
+            .filter(|(_cpu, load)| *load > min_load + tolerance)
+            .sorted_by_key(|(cpu, load)| (std::cmp::Reverse(*load), *cpu))
+            .collect();
+        let candidate = sources.iter().find_map(|(source_cpu, source_load)| {
+            // a move has to reduce the difference, then prefer the least loaded CPUs, then
+            // pots already moved, to change as few pots as possible, then the CPUs fitting
+            // the topology policy
+            loads
+                .iter()
+                .filter(|(_cpu, load)| *load < source_load)
+                .flat_map(|(target_cpu, target_load)| {
+                    new_allocations
+                        .iter()
+                        .filter(|(name, _cpus)| movable.contains(name))
+                        .filter(|(_name, cpus)| {
+                            cpus.contains(source_cpu) && !cpus.contains(target_cpu)
+                        })
+                        .filter(|(name, _cpus)| {
+                            target_load + get_weight(weights, name) < *source_load
+                        })
+                        .map(|(name, cpus)| {
+                            let other_cpus: Allocation =
+                                cpus.iter().copied().filter(|x| x != source_cpu).collect();
+                            (
+                                *target_load,
+                                !moved_pots.contains(name),
+                                placement.affinity(&other_cpus, *target_cpu),
+                                name.clone(),
+                                *target_cpu,
+                                *source_cpu,
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .min()
+        });
+        let Some((_load, _moved, _affinity, pot_name, target_cpu, source_cpu)) = candidate else {
+            break;
+        };
+        let cpus = new_allocations.get_mut(&pot_name).unwrap();
+        cpus.retain(|cpu| *cpu != source_cpu);
+        cpus.push(target_cpu);
+        cpus.sort_unstable();
+        let weight = get_weight(weights, &pot_name);
+        *loads.get_mut(&source_cpu).unwrap() -= weight;
+        *loads.get_mut(&target_cpu).unwrap() += weight;
+        if !moved_pots.contains(&pot_name) {
+            moved_pots.push(pot_name);
+        }
+    }
+    (new_allocations, loads)
+}
+
+/// The pots whose allocation changed, with their new allocation, sorted by name
+fn get_changed_allocations(
+    allocations: &HashMap<String, Allocation>,
+    new_allocations: HashMap<String, Allocation>,
+) -> Vec<(String, Allocation)> {
+    new_allocations
+        .into_iter()
+        .filter(|(name, cpus)| {
+            let mut old_cpus = allocations.get(name).unwrap().clone();
+            old_cpus.sort_unstable();
+            *cpus != old_cpus
+        })
+        .sorted_by(|a, b| a.0.cmp(&b.0))
+        .collect()
+}
+
+/// Move CPUs of the constrained pots from the most loaded CPUs to the least loaded ones,
+/// until the load difference is within the tolerance, changing as few pots as possible
+///
+/// The greedy rebalance of all the pots is an upper bound: smaller sets of pots are tried
+/// by increasing size, as long as the amount of sets is below MAX_REBALANCE_SUBSETS.
+/// It returns only the pots whose allocation changed, with their new allocation
+fn get_minimal_rebalance(
+    allocations: &HashMap<String, Allocation>,
+    cpu_loads: &HashMap<u32, u32>,
+    weights: &HashMap<String, u32>,
+    tolerance: u32,
+    placement: &CpuPlacement,
+) -> Vec<(String, Allocation)> {
+    let pot_names: Vec<&String> = allocations.keys().sorted().collect();
+    let (new_allocations, loads) = rebalance_pots(
+        allocations,
+        cpu_loads,
+        weights,
+        tolerance,
+        placement,
+        &pot_names,
+    );
+    let greedy = get_changed_allocations(allocations, new_allocations);
+    if !is_balanced(&loads, tolerance) {
+        // the tolerance can't be reached, the greedy rebalance is the best effort
+        return greedy;
+    }
+    for size in 1..greedy.len() {
+        if get_combinations(pot_names.len(), size) > MAX_REBALANCE_SUBSETS {
+            break;
+        }
+        // among the sets of the same size, the one leaving the smallest load difference
+        let best = pot_names
+            .iter()
+            .copied()
+            .combinations(size)
+            .filter_map(|subset| {
+                let (new_allocations, loads) = rebalance_pots(
+                    allocations,
+                    cpu_loads,
+                    weights,
+                    tolerance,
+                    placement,
+                    &subset,
+                );
+                is_balanced(&loads, tolerance).then(|| {
+                    let difference = loads.values().max().unwrap() - loads.values().min().unwrap();
+                    (
+                        difference,
+                        get_changed_allocations(allocations, new_allocations),
+                    )
+                })
+            })
+            .min();
+        if let Some((_difference, result)) = best {
+            return result;
+        }
+    }
+    greedy
+}
+
+/// Adapt the allocations to the amount of CPUs requested by the pots, updating the loads
+fn fix_allocation_sizes(
+    allocations: &HashMap<String, Allocation>,
+    constraints: &HashMap<String, u32>,
+    cpu_loads: &mut HashMap<u32, u32>,
+    weights: &HashMap<String, u32>,
+    placement: &CpuPlacement,
+) -> HashMap<String, Allocation> {
+    let mut result = allocations.clone();
+    for pot_name in allocations.keys().sorted() {
+        let Some(amount) = constraints.get(pot_name) else {
+            continue;
+        };
+        let amount = *amount as usize;
+        let weight = get_weight(weights, pot_name);
+        let cpus = result.get_mut(pot_name).unwrap();
+        // release the CPUs that can't be used anymore
+        cpus.retain(|cpu| cpu_loads.contains_key(cpu));
+        while cpus.len() > amount {
+            // release the most loaded CPU
+            let (index, cpu) = cpus
+                .iter()
+                .copied()
+                .enumerate()
+                .max_by_key(|(_index, cpu)| (cpu_loads.get(cpu).copied(), *cpu))
+                .unwrap();
+            cpus.remove(index);
+            if let Some(load) = cpu_loads.get_mut(&cpu) {
+                *load = load.saturating_sub(weight);
+            }
+        }
+        if cpus.len() < amount {
+            let candidates: HashMap<u32, u32> = cpu_loads
+                .iter()
+                .filter(|(cpu, _load)| !cpus.contains(cpu))
+                .map(|(cpu, load)| (*cpu, *load))
+                .collect();
+            for cpu in placement.select(amount - cpus.len(), &candidates) {
+                *cpu_loads.get_mut(&cpu).unwrap() += weight;
+                cpus.push(cpu);
+            }
+        }
+        cpus.sort_unstable();
+    }
+    result
+}
+
+/// Compute the new allocation layout, if a rebalance is needed
+fn get_rebalance_plan(
+    conf: &PotSystemConfig,
+    cmd_opt: &RebalanceOpt,
+    placement: &CpuPlacement,
+    ledger: &Ledger,
+) -> Result<Option<Vec<(String, Allocation)>>> {
+    let tolerance = cmd_opt
+        .tolerance
+        .unwrap_or_else(|| cmd_opt.load_model.default_tolerance());
+    let ncpu = get_ncpu()?;
+    let pot_allocations = get_cpusets(conf)?;
+    let weights = get_load_weights(&pot_allocations, cmd_opt.load_model, cmd_opt.interval)?;
+    // the reserved allocations load their CPUs, but they are not moved
+    let mut loaded_allocations = pot_allocations.clone();
+    add_reserved_allocations(&mut loaded_allocations, ledger);
+    let mut cpu_counters = get_cpu_loads(&loaded_allocations, &weights, ncpu);
+    let potcpu_conf = get_potcpu_conf()?;
+    remove_unavailable_cpus(
+        &mut cpu_counters,
+        &potcpu_conf,
+        ledger,
+        &loaded_allocations,
+        ncpu,
+    );
+    if cpu_counters.is_empty() {
+        bail!("no CPU left to share: all of them are host-reserved or used by exclusive pots");
+    }
+    let pot_constraints = get_declared_potcpuconstraints(conf, &pot_allocations)?;
+    // exclusive pots are never moved
+    let constrained_allocations: HashMap<String, Allocation> = pot_allocations
+        .iter()
+        .filter(|(pot_name, _)| pot_constraints.contains_key(*pot_name))
+        .filter(|(pot_name, _)| !is_exclusive(pot_name, &potcpu_conf, ledger))
+        .map(|(pot_name, allocation)| {
+            let mut allocation = allocation.clone();
+            allocation.sort_unstable();
+            (pot_name.clone(), allocation)
+        })
+        .collect();
+    let fixed_allocations = fix_allocation_sizes(
+        &constrained_allocations,
+        &pot_constraints,
+        &mut cpu_counters,
+        &weights,
+        placement,
+    );
     let min = cpu_counters
         .iter()
         .min_by_key(|(_cpu, allocation)| *allocation)
@@ -197,50 +1090,254 @@ fn rebalance(_opt: &Opt, conf: &PotSystemConfig) -> Result<()> {
         .iter()
         .max_by_key(|(_cpu, allocation)| *allocation)
         .unwrap();
-    if (max.1 - min.1) <= 1 {
+    if (max.1 - min.1) <= tolerance {
+        if fixed_allocations == constrained_allocations {
+            return Ok(None);
+        }
+        info!("rebalance needed : some pots don't use the declared amount of CPUs");
+    } else {
+        info!("rebalance needed : min {} max {}", min.1, max.1);
+    }
+    let mut new_allocations = fixed_allocations.clone();
+    new_allocations.extend(get_minimal_rebalance(
+        &fixed_allocations,
+        &cpu_counters,
+        &weights,
+        tolerance,
+        placement,
+    ));
+    Ok(Some(
+        new_allocations
+            .into_iter()
+            .filter(|(pot_name, allocation)| {
+                constrained_allocations.get(pot_name) != Some(allocation)
+            })
+            .sorted_by(|a, b| a.0.cmp(&b.0))
+            .collect(),
+    ))
+}
+
+/// Set the cpuset of a pot and verify it by reading it back
+fn set_pot_allocation(
+    runner: &dyn CommandRunner,
+    pot_name: &str,
+    allocation: &AllocationRef,
+) -> Result<()> {
+    let cpulist = allocation_to_cpulist(allocation);
+    runner.run("/usr/bin/cpuset", &["-l", &cpulist, "-j", pot_name])?;
+    let output = runner.run("/usr/bin/cpuset", &["-g", "-j", pot_name])?;
+    let mut new_allocation = allocation_from_utf8(output.as_bytes())?;
+    new_allocation.sort_unstable();
+    let mut expected = allocation.to_vec();
+    expected.sort_unstable();
+    if new_allocation != expected {
+        bail!(
+            "pot {} has cpuset {} instead of {}",
+            pot_name,
+            allocation_to_cpulist(&new_allocation),
+            cpulist
+        );
+    }
+    Ok(())
+}
+
+/// Apply the plan; if a pot fails, the pots already modified are restored
+fn apply_rebalance_plan(
+    runner: &dyn CommandRunner,
+    plan: &[(String, Allocation)],
+    previous: &HashMap<String, Allocation>,
+) -> Result<()> {
+    let mut applied: Vec<&str> = Vec::new();
+    for (pot_name, allocation) in plan {
+        info!(
+            "pot {}: applying cpuset {}",
+            pot_name,
+            allocation_to_cpulist(allocation)
+        );
+        if let Err(e) = set_pot_allocation(runner, pot_name, allocation) {
+            error!("pot {}: failed to apply the new cpuset: {}", pot_name, e);
+            applied.push(pot_name);
+            for rollback_pot in applied.iter().rev() {
+                let Some(old_allocation) = previous.get(*rollback_pot) else {
+                    continue;
+                };
+                info!("pot {}: rollback", rollback_pot);
+                if let Err(e) = set_pot_allocation(runner, rollback_pot, old_allocation) {
+                    error!("pot {}: rollback failed: {}", rollback_pot, e);
+                }
+            }
+            bail!("rebalance failed on pot {}", pot_name);
+        }
+        applied.push(pot_name);
+    }
+    Ok(())
+}
+
+fn rebalance(_opt: &Opt, conf: &PotSystemConfig, cmd_opt: RebalanceOpt) -> Result<()> {
+    let placement = CpuPlacement::new(cmd_opt.policy);
+    let ledger_dir = get_ledger_dir(conf);
+    let _lock = LedgerLock::acquire(&ledger_dir)?;
+    let ledger = get_reconciled_ledger(conf, &ledger_dir.join("ledger"))?;
+    let Some(plan) = get_rebalance_plan(conf, &cmd_opt, &placement, &ledger)? else {
         warn!("no need to rebalance");
         return Ok(());
+    };
+    if plan.is_empty() {
+        warn!("rebalance needed, but no pot can be moved");
+        return Ok(());
+    }
+    if cmd_opt.apply && !cmd_opt.dry_run {
+        let previous = get_cpusets(conf)?;
+        apply_rebalance_plan(&SystemRunner, &plan, &previous)?;
+        println!("rebalance applied: {} pots moved", plan.len());
     } else {
-        info!("rebalance needed : min {} max {}", min.1, max.1);
+        println!("# {} pots to move", plan.len());
+        for (pot_name, pot_allocation) in plan {
+            println!(
+                "cpuset -l {} -j {}",
+                allocation_to_cpulist(&pot_allocation),
+                pot_name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Compute the target layout of all the pots with a CPU constraint, running or not
+///
+/// The stopped pots start from their reserved CPUs, if any, then the layout is rebalanced.
+/// The exclusive pots, running or with reserved CPUs, are never moved
+fn get_fleet_plan(
+    running: &HashMap<String, Allocation>,
+    constraints: &HashMap<String, u32>,
+    ledger: &Ledger,
+    potcpu_conf: &PotcpuConf,
+    ncpu: u32,
+    tolerance: u32,
+    placement: &CpuPlacement,
+) -> BTreeMap<String, Allocation> {
+    let mut result = BTreeMap::new();
+    let mut cpu_loads = get_cpu_loads(running, &HashMap::new(), ncpu);
+    let mut pinned = running.clone();
+    add_reserved_allocations(&mut pinned, ledger);
+    remove_unavailable_cpus(&mut cpu_loads, potcpu_conf, ledger, &pinned, ncpu);
+    let mut allocations: HashMap<String, Allocation> = HashMap::new();
+    for pot_name in constraints.keys() {
+        if let Some(cpus) = pinned.get(pot_name) {
+            if is_exclusive(pot_name, potcpu_conf, ledger) {
+                result.insert(pot_name.clone(), cpus.clone());
+                continue;
+            }
+        }
+        let mut cpus = match running.get(pot_name) {
+            Some(cpus) => cpus.clone(),
+            None => {
+                let cpus = ledger
+                    .get(pot_name)
+                    .map(|reservation| reservation.cpus.clone())
+                    .unwrap_or_default();
+                for cpu in &cpus {
+                    if let Some(load) = cpu_loads.get_mut(cpu) {
+                        *load += 1;
+                    }
+                }
+                cpus
+            }
+        };
+        cpus.sort_unstable();
+        allocations.insert(pot_name.clone(), cpus);
     }
+    let fixed_allocations = fix_allocation_sizes(
+        &allocations,
+        constraints,
+        &mut cpu_loads,
+        &HashMap::new(),
+        placement,
+    );
+    result.extend(fixed_allocations.clone());
+    result.extend(get_minimal_rebalance(
+        &fixed_allocations,
+        &cpu_loads,
+        &HashMap::new(),
+        tolerance,
+        placement,
+    ));
+    result
+}
+
+fn plan(conf: &PotSystemConfig, cmd_opt: PlanOpt) -> Result<()> {
     let ncpu = get_ncpu()?;
-    let pot_allocations = get_cpusets(conf)?;
-    let pot_constraints = get_potcpuconstraints(&pot_allocations)?;
-    let mut pot_new_allocations = HashMap::new();
-    let mut cpu_index_counter: u32 = 0;
-    for (pot_name, amount_cpu) in pot_constraints.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
-        let mut cpus: Vec<u32> = Vec::new();
-        for _ in 0..*amount_cpu {
-            cpus.push(cpu_index_counter);
-            cpu_index_counter += 1;
-            cpu_index_counter %= ncpu;
-        }
-        pot_new_allocations.insert(pot_name, cpus);
-    }
-    for (pot_name, pot_allocation) in pot_new_allocations {
-        let mut cpuset_string = String::new();
-        for cpu in pot_allocation {
-            cpuset_string.push_str(&cpu.to_string());
-            cpuset_string.push(',');
-        }
-        println!(
-            "cpuset -l {} -j {}",
-            cpuset_string.trim_end_matches(','),
-            pot_name
-        );
+    let running = get_cpusets(conf)?;
+    let mut constraints = get_declared_potcpuconstraints(conf, &running)?;
+    for pot_name in get_pot_list(conf) {
+        if running.contains_key(&pot_name) {
+            continue;
+        }
+        if let Some(cpus) = get_declared_cpus(conf, &pot_name) {
+            constraints.insert(pot_name, cpus.min(ncpu));
+        }
+    }
+    let ledger_dir = get_ledger_dir(conf);
+    let _lock = LedgerLock::acquire(&ledger_dir)?;
+    let ledger = get_reconciled_ledger(conf, &ledger_dir.join("ledger"))?;
+    let placement = CpuPlacement::new(cmd_opt.policy);
+    let target = get_fleet_plan(
+        &running,
+        &constraints,
+        &ledger,
+        &get_potcpu_conf()?,
+        ncpu,
+        cmd_opt.tolerance,
+        &placement,
+    );
+    let mut moves = Vec::new();
+    for (pot_name, cpus) in &target {
+        match running.get(pot_name) {
+            Some(current) => {
+                let mut current = current.clone();
+                current.sort_unstable();
+                if current == *cpus {
+                    println!(
+                        "pot {}: {} (running)",
+                        pot_name,
+                        allocation_to_cpulist(cpus)
+                    );
+                } else {
+                    println!(
+                        "pot {}: {} (running, currently {})",
+                        pot_name,
+                        allocation_to_cpulist(cpus),
+                        allocation_to_cpulist(&current)
+                    );
+                    moves.push((pot_name, cpus));
+                }
+            }
+            None => println!(
+                "pot {}: {} (stopped)",
+                pot_name,
+                allocation_to_cpulist(cpus)
+            ),
+        }
+    }
+    println!("# {} running pots to move", moves.len());
+    for (pot_name, cpus) in moves {
+        println!("cpuset -l {} -j {}", allocation_to_cpulist(cpus), pot_name);
     }
     Ok(())
 }
+
 fn main() -> Result<()> {
     let opt = Opt::from_args();
     opt.verbose.set_log_level();
     trace!("potcpu start");
 
     let conf = PotSystemConfig::from_system()?;
-    match opt.subcommand {
+    match &opt.subcommand {
         Command::Show => show(&opt, &conf)?,
-        Command::GetCpu(cmd_opt) => get_cpu(&opt, &conf, cmd_opt.cpu_amount)?,
-        Command::Rebalance => rebalance(&opt, &conf)?,
+        Command::GetCpu(cmd_opt) => get_cpu(&opt, &conf, cmd_opt)?,
+        Command::ReleaseCpu(cmd_opt) => release_cpu(&conf, cmd_opt)?,
+        Command::Rebalance(cmd_opt) => rebalance(&opt, &conf, *cmd_opt)?,
+        Command::Plan(cmd_opt) => plan(&conf, *cmd_opt)?,
     }
     Ok(())
 }
@@ -261,6 +1358,11 @@ mod tests {
         assert_eq!(result[0], 0);
         assert_eq!(result[7], 7);
 
+        let test_str = "jail 1 domain policy: first-touch mask: 0\njail 1 mask: 0-3, 8-11";
+        let result = allocation_from_utf8(test_str.as_bytes());
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), vec![0, 1, 2, 3, 8, 9, 10, 11]);
+
         let error1 = allocation_from_utf8("".as_bytes());
         assert!(error1.is_err());
         let error2 = allocation_from_utf8("no jail mask".as_bytes());
@@ -324,4 +1426,563 @@ mod tests {
         assert_eq!(result.get(&2).unwrap(), &1);
         assert_eq!(result.get(&3).unwrap(), &1);
     }
+
+    struct TestRunner {
+        cpusets: std::cell::RefCell<HashMap<String, String>>,
+        broken_pot: &'static str,
+    }
+
+    impl CommandRunner for TestRunner {
+        fn run(&self, _program: &str, args: &[&str]) -> pot_rs::Result<String> {
+            let pot_name = args[args.len() - 1].to_string();
+            if args[0] == "-l" {
+                if pot_name != self.broken_pot {
+                    self.cpusets
+                        .borrow_mut()
+                        .insert(pot_name, args[1].replace(',', ", "));
+                }
+                Ok(String::new())
+            } else {
+                Ok(format!(
+                    "jail {} mask: {}\n",
+                    pot_name,
+                    self.cpusets.borrow().get(&pot_name).unwrap()
+                ))
+            }
+        }
+    }
+
+    #[test]
+    fn test_apply_rebalance_plan() {
+        let mut previous = HashMap::new();
+        previous.insert("pot1".to_string(), vec![0]);
+        previous.insert("pot2".to_string(), vec![0]);
+        previous.insert("pot3".to_string(), vec![0]);
+        let runner = TestRunner {
+            cpusets: std::cell::RefCell::new(
+                previous
+                    .keys()
+                    .map(|x| (x.to_string(), "0".to_string()))
+                    .collect(),
+            ),
+            broken_pot: "none",
+        };
+        let plan = vec![
+            ("pot2".to_string(), vec![1]),
+            ("pot3".to_string(), vec![2, 3]),
+        ];
+        let result = apply_rebalance_plan(&runner, &plan, &previous);
+        assert!(result.is_ok());
+        assert_eq!(runner.cpusets.borrow().get("pot1").unwrap(), "0");
+        assert_eq!(runner.cpusets.borrow().get("pot2").unwrap(), "1");
+        assert_eq!(runner.cpusets.borrow().get("pot3").unwrap(), "2-3");
+
+        let runner = TestRunner {
+            cpusets: std::cell::RefCell::new(
+                previous
+                    .keys()
+                    .map(|x| (x.to_string(), "0".to_string()))
+                    .collect(),
+            ),
+            broken_pot: "pot3",
+        };
+        let result = apply_rebalance_plan(&runner, &plan, &previous);
+        assert!(result.is_err());
+        assert_eq!(runner.cpusets.borrow().get("pot2").unwrap(), "0");
+        assert_eq!(runner.cpusets.borrow().get("pot3").unwrap(), "0");
+    }
+
+    #[test]
+    fn test_get_minimal_rebalance() {
+        let mut allocations = HashMap::new();
+        allocations.insert("pot0".to_string(), vec![0]);
+        allocations.insert("pot01".to_string(), vec![0, 1]);
+        allocations.insert("pot0b".to_string(), vec![0]);
+        allocations.insert("pot1".to_string(), vec![1]);
+        let mut loads = HashMap::new();
+        loads.insert(0, 3);
+        loads.insert(1, 2);
+        loads.insert(2, 0);
+        loads.insert(3, 0);
+        let placement = CpuPlacement {
+            topology: None,
+            policy: Policy::Pack,
+        };
+        let result = get_minimal_rebalance(&allocations, &loads, &HashMap::new(), 1, &placement);
+        // moving pot01 alone from CPU 0 and 1 reaches the tolerance
+        assert_eq!(result, vec![("pot01".to_string(), vec![2, 3])]);
+        let result = get_minimal_rebalance(&allocations, &loads, &HashMap::new(), 3, &placement);
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_get_combinations() {
+        assert_eq!(get_combinations(4, 0), 1);
+        assert_eq!(get_combinations(4, 1), 4);
+        assert_eq!(get_combinations(4, 2), 6);
+        assert_eq!(get_combinations(30, 15), 155_117_520);
+    }
+
+    #[test]
+    fn test_get_minimal_rebalance_no_candidates() {
+        let mut allocations = HashMap::new();
+        allocations.insert("pot01".to_string(), vec![0, 1]);
+        let mut loads = HashMap::new();
+        loads.insert(0, 1);
+        loads.insert(1, 1);
+        let placement = CpuPlacement {
+            topology: None,
+            policy: Policy::Pack,
+        };
+        let result = get_minimal_rebalance(&allocations, &loads, &HashMap::new(), 0, &placement);
+        assert!(result.is_empty());
+    }
+
+    /// Two packages, with two SMT cores each (the XML parsing is tested in pot-rs)
+    fn dual_socket_topology() -> CpuTopology {
+        let core = |cpus: &[u32]| CpuGroup {
+            level: 3,
+            cache_level: 2,
+            cpus: cpus.to_vec(),
+            flags: vec!["SMT".to_string()],
+            children: Vec::new(),
+        };
+        let package = |cpus: &[u32]| CpuGroup {
+            level: 2,
+            cache_level: 3,
+            cpus: cpus.to_vec(),
+            flags: Vec::new(),
+            children: cpus.chunks(2).map(core).collect(),
+        };
+        CpuTopology {
+            root: CpuGroup {
+                level: 1,
+                cache_level: 0,
+                cpus: (0..8).collect(),
+                flags: Vec::new(),
+                children: vec![package(&[0, 1, 2, 3]), package(&[4, 5, 6, 7])],
+            },
+        }
+    }
+
+    #[test]
+    fn test_cpu_placement_select() {
+        let mut loads: HashMap<u32, u32> = (0..8).map(|cpu| (cpu, 0)).collect();
+        loads.insert(0, 2);
+        loads.insert(4, 1);
+        let pack = CpuPlacement {
+            topology: Some(dual_socket_topology()),
+            policy: Policy::Pack,
+        };
+        let spread = CpuPlacement {
+            topology: Some(dual_socket_topology()),
+            policy: Policy::Spread,
+        };
+        let flat = CpuPlacement {
+            topology: None,
+            policy: Policy::Pack,
+        };
+        // the two threads of the idle core of the least loaded package
+        assert_eq!(pack.select(2, &loads), vec![6, 7]);
+        // the whole least loaded package
+        assert_eq!(pack.select(4, &loads), vec![5, 6, 7, 4]);
+        // one core per package, avoiding the SMT siblings
+        assert_eq!(spread.select(4, &loads), vec![6, 5, 2, 1]);
+        assert_eq!(flat.select(4, &loads), vec![1, 2, 3, 5]);
+    }
+
+    #[test]
+    fn test_cpu_placement_affinity() {
+        let pack = CpuPlacement {
+            topology: Some(dual_socket_topology()),
+            policy: Policy::Pack,
+        };
+        let spread = CpuPlacement {
+            topology: Some(dual_socket_topology()),
+            policy: Policy::Spread,
+        };
+        assert!(pack.affinity(&[0], 1) < pack.affinity(&[0], 2));
+        assert!(pack.affinity(&[0], 2) < pack.affinity(&[0], 4));
+        assert!(spread.affinity(&[0], 4) < spread.affinity(&[0], 2));
+        assert_eq!(pack.affinity(&[], 4), pack.affinity(&[], 2));
+    }
+
+    #[test]
+    fn test_get_declared_potcpuconstraints() {
+        use mocktopus::mocking::*;
+        get_ncpu.mock_safe(|| MockResult::Return(Ok(4)));
+        get_declared_cpus.mock_safe(|_, pot_name| {
+            MockResult::Return(match pot_name {
+                "pot-declared" => Some(2),
+                "pot-all" => Some(8),
+                _ => None,
+            })
+        });
+        let mut allocations = HashMap::new();
+        allocations.insert("pot-declared".to_string(), vec![0, 1, 2, 3]);
+        allocations.insert("pot-all".to_string(), vec![0]);
+        allocations.insert("pot-inferred".to_string(), vec![1]);
+        let conf = PotSystemConfig::default();
+        let result = get_declared_potcpuconstraints(&conf, &allocations);
+        assert!(result.is_ok());
+        let result = result.unwrap();
+        assert_eq!(result.len(), 3);
+        assert_eq!(result.get("pot-declared"), Some(&2));
+        assert_eq!(result.get("pot-all"), Some(&4));
+        assert_eq!(result.get("pot-inferred"), Some(&1));
+    }
+
+    #[test]
+    fn test_check_declared_cpus() {
+        assert_eq!(check_declared_cpus("pot-zero", Some(0)), None);
+        assert_eq!(check_declared_cpus("pot-one", Some(1)), Some(1));
+        assert_eq!(check_declared_cpus("pot-none", None), None);
+    }
+
+    #[test]
+    fn test_get_rebalance_plan_no_shareable_cpu() {
+        use mocktopus::mocking::*;
+        get_ncpu.mock_safe(|| MockResult::Return(Ok(4)));
+        get_cpusets.mock_safe(|_| {
+            let mut pot_cpusets = HashMap::new();
+            pot_cpusets.insert("excl".to_string(), vec![2, 3]);
+            pot_cpusets.insert("shared".to_string(), vec![2]);
+            MockResult::Return(Ok(pot_cpusets))
+        });
+        get_potcpu_conf.mock_safe(|| {
+            MockResult::Return(Ok(PotcpuConf {
+                reserved_cpus: vec![0, 1],
+                exclusive_pots: vec!["excl".to_string()],
+            }))
+        });
+        get_declared_cpus.mock_safe(|_, _| MockResult::Return(None));
+        let cmd_opt = RebalanceOpt {
+            apply: false,
+            dry_run: false,
+            tolerance: Some(1),
+            policy: Policy::Pack,
+            load_model: LoadModel::Count,
+            interval: 5,
+        };
+        let placement = CpuPlacement {
+            topology: None,
+            policy: Policy::Pack,
+        };
+        let conf = PotSystemConfig::default();
+        let result = get_rebalance_plan(&conf, &cmd_opt, &placement, &Ledger::new());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_fix_allocation_sizes() {
+        let mut allocations = HashMap::new();
+        allocations.insert("pot-shrink".to_string(), vec![0, 1, 2, 3]);
+        allocations.insert("pot-grow".to_string(), vec![1]);
+        allocations.insert("pot-ok".to_string(), vec![0]);
+        let mut constraints = HashMap::new();
+        constraints.insert("pot-shrink".to_string(), 1);
+        constraints.insert("pot-grow".to_string(), 2);
+        constraints.insert("pot-ok".to_string(), 1);
+        let mut loads = HashMap::new();
+        loads.insert(0, 2);
+        loads.insert(1, 2);
+        loads.insert(2, 1);
+        loads.insert(3, 1);
+        let placement = CpuPlacement {
+            topology: None,
+            policy: Policy::Pack,
+        };
+        let result = fix_allocation_sizes(
+            &allocations,
+            &constraints,
+            &mut loads,
+            &HashMap::new(),
+            &placement,
+        );
+        // pot-grow is evaluated first and gets CPU 2, then pot-shrink keeps only CPU 3
+        assert_eq!(result.get("pot-grow"), Some(&vec![1, 2]));
+        assert_eq!(result.get("pot-shrink"), Some(&vec![3]));
+        assert_eq!(result.get("pot-ok"), Some(&vec![0]));
+        assert_eq!(loads.get(&0), Some(&1));
+        assert_eq!(loads.get(&1), Some(&1));
+        assert_eq!(loads.get(&2), Some(&1));
+        assert_eq!(loads.get(&3), Some(&1));
+    }
+
+    #[test]
+    fn test_potcpu_conf_fromstr() {
+        let uut = PotcpuConf::from_str("");
+        assert!(uut.is_ok());
+        assert_eq!(uut.unwrap(), PotcpuConf::default());
+        let uut = PotcpuConf::from_str(
+            "# host CPUs\nreserved_cpus=0, 1 # comment\nexclusive_pots=db1,web1\nfoo=bar",
+        );
+        assert!(uut.is_ok());
+        let uut = uut.unwrap();
+        assert_eq!(uut.reserved_cpus, vec![0, 1]);
+        assert_eq!(
+            uut.exclusive_pots,
+            vec!["db1".to_string(), "web1".to_string()]
+        );
+        let uut = PotcpuConf::from_str("reserved_cpus=zero");
+        assert!(uut.is_err());
+    }
+
+    #[test]
+    fn test_remove_unavailable_cpus() {
+        let mut loads: HashMap<u32, u32> = (0..8).map(|cpu| (cpu, 1)).collect();
+        let potcpu_conf = PotcpuConf {
+            reserved_cpus: vec![0, 1],
+            exclusive_pots: vec!["db1".to_string(), "web1".to_string()],
+        };
+        let mut allocations = HashMap::new();
+        allocations.insert("db1".to_string(), vec![4, 5]);
+        allocations.insert("web1".to_string(), (0..8).collect());
+        allocations.insert("pot1".to_string(), vec![6]);
+        allocations.insert("pot2".to_string(), vec![3]);
+        let mut ledger = Ledger::new();
+        ledger.insert("pot2".to_string(), reservation(vec![3], true));
+        remove_unavailable_cpus(&mut loads, &potcpu_conf, &Ledger::new(), &allocations, 8);
+        assert_eq!(
+            loads.keys().copied().sorted().collect::<Vec<u32>>(),
+            vec![2, 3, 6, 7]
+        );
+        remove_unavailable_cpus(&mut loads, &potcpu_conf, &ledger, &allocations, 8);
+        assert_eq!(
+            loads.keys().copied().sorted().collect::<Vec<u32>>(),
+            vec![2, 6, 7]
+        );
+    }
+
+    #[test]
+    fn test_get_cpu_loads() {
+        let mut allocations = HashMap::new();
+        allocations.insert("pot1".to_string(), vec![0, 1]);
+        allocations.insert("pot2".to_string(), vec![1]);
+        let result = get_cpu_loads(&allocations, &HashMap::new(), 3);
+        assert_eq!(result.get(&0), Some(&1));
+        assert_eq!(result.get(&1), Some(&2));
+        assert_eq!(result.get(&2), Some(&0));
+        let mut weights = HashMap::new();
+        weights.insert("pot1".to_string(), 40);
+        let result = get_cpu_loads(&allocations, &weights, 3);
+        assert_eq!(result.get(&0), Some(&40));
+        assert_eq!(result.get(&1), Some(&41));
+        assert_eq!(result.get(&2), Some(&0));
+    }
+
+    struct UsageRunner;
+
+    impl CommandRunner for UsageRunner {
+        fn run(&self, program: &str, args: &[&str]) -> pot_rs::Result<String> {
+            assert_eq!(program, "/usr/bin/rctl");
+            match args {
+                ["-u", "jail:busy"] => Ok("cputime=120\npcpu=150\nmemoryuse=4096\n".to_string()),
+                ["-u", "jail:idle"] => Ok("cputime=0\npcpu=0\nmemoryuse=4096\n".to_string()),
+                _ => Ok(String::new()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_usage_weights() {
+        let usages = sample_pot_usages(&UsageRunner, &["busy", "idle", "unknown"], 1);
+        assert!(usages.is_ok());
+        let usages = usages.unwrap();
+        assert_eq!(usages.get("busy"), Some(&150));
+        assert_eq!(usages.get("idle"), Some(&0));
+        assert_eq!(usages.get("unknown"), Some(&0));
+        let mut allocations = HashMap::new();
+        allocations.insert("busy".to_string(), vec![0, 1]);
+        allocations.insert("idle".to_string(), vec![2]);
+        let result = get_usage_weights(&allocations, &usages);
+        assert_eq!(result.get("busy"), Some(&75));
+        assert_eq!(result.get("idle"), Some(&1));
+    }
+
+    struct NoisyUsageRunner;
+
+    impl CommandRunner for NoisyUsageRunner {
+        fn run(&self, _program: &str, args: &[&str]) -> pot_rs::Result<String> {
+            let pcpu = match args {
+                ["-u", "jail:web1"] => 30,
+                ["-u", "jail:web2"] => 3,
+                _ => 28,
+            };
+            Ok(format!("pcpu={}\n", pcpu))
+        }
+    }
+
+    #[test]
+    fn test_get_minimal_rebalance_usage_tolerance() {
+        let mut allocations = HashMap::new();
+        allocations.insert("web1".to_string(), vec![0]);
+        allocations.insert("web2".to_string(), vec![0]);
+        allocations.insert("db1".to_string(), vec![1]);
+        let usages = sample_pot_usages(&NoisyUsageRunner, &["db1", "web1", "web2"], 1).unwrap();
+        let weights = get_usage_weights(&allocations, &usages);
+        let loads = get_cpu_loads(&allocations, &weights, 2);
+        let placement = CpuPlacement {
+            topology: None,
+            policy: Policy::Pack,
+        };
+        // 33 and 28 percent: with the default tolerance nothing is worth moving
+        let tolerance = LoadModel::Usage.default_tolerance();
+        let result = get_minimal_rebalance(&allocations, &loads, &weights, tolerance, &placement);
+        assert!(result.is_empty());
+        let result = get_minimal_rebalance(&allocations, &loads, &weights, 1, &placement);
+        assert_eq!(result, vec![("web2".to_string(), vec![1])]);
+    }
+
+    #[test]
+    fn test_get_minimal_rebalance_weighted() {
+        let mut allocations = HashMap::new();
+        allocations.insert("busy".to_string(), vec![0]);
+        allocations.insert("idle1".to_string(), vec![1]);
+        allocations.insert("idle2".to_string(), vec![1]);
+        allocations.insert("idle3".to_string(), vec![1]);
+        let mut weights = HashMap::new();
+        weights.insert("busy".to_string(), 90);
+        let loads = get_cpu_loads(&allocations, &weights, 3);
+        let placement = CpuPlacement {
+            topology: None,
+            policy: Policy::Pack,
+        };
+        // counting pots, CPU 1 is the most loaded one
+        let count_loads = get_cpu_loads(&allocations, &HashMap::new(), 3);
+        let result =
+            get_minimal_rebalance(&allocations, &count_loads, &HashMap::new(), 1, &placement);
+        assert_eq!(result, vec![("idle1".to_string(), vec![2])]);
+        // with the usage, the busy pot is already alone and moving it wouldn't help
+        let result = get_minimal_rebalance(&allocations, &loads, &weights, 10, &placement);
+        assert!(result.is_empty());
+    }
+
+    fn reservation(cpus: Allocation, exclusive: bool) -> Reservation {
+        Reservation { cpus, exclusive }
+    }
+
+    #[test]
+    fn test_reconcile_ledger() {
+        let mut ledger = Ledger::new();
+        ledger.insert("pending".to_string(), reservation(vec![0], false));
+        ledger.insert("started".to_string(), reservation(vec![1], false));
+        ledger.insert("destroyed".to_string(), reservation(vec![2], false));
+        ledger.insert("isolated".to_string(), reservation(vec![3], true));
+        let pots = vec![
+            "pending".to_string(),
+            "started".to_string(),
+            "isolated".to_string(),
+        ];
+        let running_pots = vec!["started".to_string(), "isolated".to_string()];
+        reconcile_ledger(&mut ledger, &pots, &running_pots);
+        assert_eq!(ledger.len(), 2);
+        assert_eq!(ledger.get("pending"), Some(&reservation(vec![0], false)));
+        assert_eq!(ledger.get("isolated"), Some(&reservation(vec![3], true)));
+    }
+
+    #[test]
+    fn test_ledger_file() {
+        let dir = std::env::temp_dir().join(format!("potcpu-test-{}", std::process::id()));
+        {
+            let lock = LedgerLock::acquire(&dir);
+            assert!(lock.is_ok());
+            let other = std::fs::File::open(dir.join("ledger.lock")).unwrap();
+            assert!(other.try_lock().is_err());
+            let path = dir.join("ledger");
+            let ledger = read_ledger(&path);
+            assert!(ledger.is_ok());
+            let mut ledger = ledger.unwrap();
+            assert!(ledger.is_empty());
+            ledger.insert("pot1".to_string(), reservation(vec![0, 1, 2, 5], false));
+            ledger.insert("pot2".to_string(), reservation(vec![3], true));
+            assert!(write_ledger(&path, &ledger).is_ok());
+            assert_eq!(
+                std::fs::read_to_string(&path).unwrap(),
+                "pot1 0-2,5\npot2 3 exclusive\n"
+            );
+            assert_eq!(read_ledger(&path).unwrap(), ledger);
+        }
+        // the lock is released with the file, even if the lock file is left behind
+        assert!(dir.join("ledger.lock").exists());
+        assert!(LedgerLock::acquire(&dir).is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_get_fleet_plan() {
+        let mut running = HashMap::new();
+        running.insert("run0".to_string(), vec![0]);
+        running.insert("run0b".to_string(), vec![0]);
+        running.insert("excl".to_string(), vec![3]);
+        running.insert("run0c".to_string(), vec![0]);
+        let mut constraints = HashMap::new();
+        constraints.insert("run0".to_string(), 1);
+        constraints.insert("run0b".to_string(), 1);
+        constraints.insert("run0c".to_string(), 1);
+        constraints.insert("excl".to_string(), 1);
+        constraints.insert("stopped".to_string(), 1);
+        constraints.insert("reserved".to_string(), 1);
+        let mut ledger = Ledger::new();
+        ledger.insert("reserved".to_string(), reservation(vec![1], false));
+        let potcpu_conf = PotcpuConf {
+            reserved_cpus: vec![],
+            exclusive_pots: vec!["excl".to_string()],
+        };
+        let placement = CpuPlacement {
+            topology: None,
+            policy: Policy::Pack,
+        };
+        let result = get_fleet_plan(
+            &running,
+            &constraints,
+            &ledger,
+            &potcpu_conf,
+            4,
+            0,
+            &placement,
+        );
+        assert_eq!(result.len(), 6);
+        assert_eq!(result.get("excl"), Some(&vec![3]));
+        assert_eq!(result.get("reserved"), Some(&vec![1]));
+        assert_eq!(result.get("stopped"), Some(&vec![2]));
+        // one of the three pots sharing CPU 0 is moved
+        assert_eq!(result.get("run0"), Some(&vec![1]));
+        assert_eq!(result.get("run0b"), Some(&vec![0]));
+        assert_eq!(result.get("run0c"), Some(&vec![0]));
+    }
+
+    #[test]
+    fn test_get_fleet_plan_exclusive_reservation() {
+        let mut running = HashMap::new();
+        running.insert("run0".to_string(), vec![0]);
+        running.insert("run0b".to_string(), vec![0]);
+        let mut constraints = HashMap::new();
+        constraints.insert("run0".to_string(), 1);
+        constraints.insert("run0b".to_string(), 1);
+        constraints.insert("isolated".to_string(), 1);
+        constraints.insert("stopped".to_string(), 1);
+        let mut ledger = Ledger::new();
+        ledger.insert("isolated".to_string(), reservation(vec![1], true));
+        let placement = CpuPlacement {
+            topology: None,
+            policy: Policy::Pack,
+        };
+        let result = get_fleet_plan(
+            &running,
+            &constraints,
+            &ledger,
+            &PotcpuConf::default(),
+            4,
+            0,
+            &placement,
+        );
+        assert_eq!(result.len(), 4);
+        assert_eq!(result.get("isolated"), Some(&vec![1]));
+        for (pot_name, cpus) in &result {
+            if pot_name != "isolated" {
+                assert!(!cpus.contains(&1));
+            }
+        }
+    }
 }
@@ -1,11 +1,22 @@
 use anyhow::{bail, Result};
 use ipnet::IpNet;
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 use pot_rs::bridge::{get_bridges_list, BridgeConf};
-use pot_rs::{get_pot_conf_list, NetType, PotSystemConfig};
-use std::collections::BTreeMap;
-use std::net::IpAddr;
+use pot_rs::command::SystemRunner;
+use pot_rs::host::{HostConflict, HostNetworks};
+use pot_rs::ipam::{
+    get_reservations, is_overlapping, AddressRole, AddressSpace, IpamError, Placement,
+};
+use pot_rs::{
+    get_conf_provenance, get_pot_aliases, get_pot_conf_list, get_pot_list, get_running_pot_list,
+    ConfProvenance, NetType, PotConf, PotSystemConfig, POT_SYSTEM_KEYS,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::net::IpAddr::{V4, V6};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::string::String;
 use structopt::StructOpt;
 use structopt_flags::{HostParam, LogLevel};
@@ -14,6 +25,9 @@ use structopt_flags::{HostParam, LogLevel};
 struct Opt {
     #[structopt(flatten)]
     verbose: structopt_flags::QuietVerbose,
+    /// Override a setting of the pot configuration, like POT_NETWORK=10.192.0.0/10
+    #[structopt(long = "--set", number_of_values = 1, parse(try_from_str = parse_setting))]
+    set: Vec<(String, String)>,
     #[structopt(subcommand)]
     subcommand: Command,
 }
@@ -25,10 +39,13 @@ enum Command {
     Show(BridgeOpt),
     /// Provides the next available IP address
     #[structopt(name = "next")]
-    Next(BridgeOpt),
+    Next(NextOpt),
     /// Check the POT config
     #[structopt(name = "config-check")]
     ConfigCheck,
+    /// Show the effective POT config, with the origin of each setting
+    #[structopt(name = "config-show")]
+    ConfigShow,
     /// Validate the IP address provided as parameter
     #[structopt(name = "validate")]
     Validate(ValidateOpt),
@@ -47,6 +64,31 @@ enum Command {
     /// Generate the etc/hosts file with all know hosts in the specific bridge
     #[structopt(name = "etc-hosts")]
     EtcHosts(BridgeOpt),
+    /// Render the network topology of bridges and pots as a diagram
+    #[structopt(name = "topology")]
+    Topology(TopologyOpt),
+    /// Report the address utilization of the pot network and of the bridges
+    #[structopt(name = "usage")]
+    Usage(UsageOpt),
+    /// Show the owner of an IP address, or the addresses of a pot
+    #[structopt(name = "whois")]
+    Whois(WhoisOpt),
+    /// Export the network state as a JSON document
+    #[structopt(name = "export")]
+    Export,
+    /// Check a document, created by export, against the network state of this host
+    #[structopt(name = "import")]
+    Import(ImportOpt),
+}
+
+fn parse_setting(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((key, value)) if POT_SYSTEM_KEYS.contains(&key) => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        Some((key, _value)) => bail!("unknown setting {}", key),
+        None => bail!("{} is not in the form KEY=VALUE", s),
+    }
 }
 
 #[derive(Clone, Debug, StructOpt)]
@@ -56,6 +98,57 @@ struct BridgeOpt {
     bridge_name: Option<String>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Strategy {
+    Lowest,
+    Highest,
+    Random,
+    Sticky,
+    SkipRecent,
+}
+
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "lowest" => Ok(Strategy::Lowest),
+            "highest" => Ok(Strategy::Highest),
+            "random" => Ok(Strategy::Random),
+            "sticky" => Ok(Strategy::Sticky),
+            "skip-recent" => Ok(Strategy::SkipRecent),
+            _ => bail!(
+                "unknown strategy {} (lowest, highest, random, sticky or skip-recent)",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, StructOpt)]
+struct NextOpt {
+    /// The name of a private bridge
+    #[structopt(short = "-b", long = "--bridge-name")]
+    bridge_name: Option<String>,
+    /// The allocation strategy: lowest, highest, random, sticky or skip-recent
+    #[structopt(short = "-S", long = "--strategy", default_value = "lowest")]
+    strategy: Strategy,
+    /// The name of the pot, needed by the sticky strategy to get always the same address
+    #[structopt(short = "-p", long = "--pot-name", required_if("strategy", "sticky"))]
+    pot_name: Option<String>,
+    /// How long (in seconds) a released address is avoided by the skip-recent strategy
+    #[structopt(long = "--quarantine", default_value = "86400")]
+    quarantine: u64,
+    /// The amount of addresses to provide
+    #[structopt(short = "-n", long = "--count", default_value = "1")]
+    count: usize,
+    /// The provided addresses have to be consecutive
+    #[structopt(short = "-c", long = "--contiguous")]
+    contiguous: bool,
+    /// The pot being reconfigured: its current addresses, DNS included, are considered free
+    #[structopt(long = "--for-pot")]
+    for_pot: Option<String>,
+}
+
 #[derive(Clone, Debug, StructOpt)]
 struct ValidateOpt {
     #[structopt(flatten)]
@@ -63,6 +156,12 @@ struct ValidateOpt {
     /// The name of the private bridge, if the IP belongs to it
     #[structopt(short = "-b", long = "--bridge-name")]
     bridge_name: Option<String>,
+    /// Explain why the IP is not valid, suggesting the nearest free address
+    #[structopt(short = "-e", long = "--explain")]
+    explain: bool,
+    /// The pot being reconfigured: its current addresses, DNS included, are considered free
+    #[structopt(long = "--for-pot")]
+    for_pot: Option<String>,
 }
 
 #[derive(Clone, Debug, StructOpt)]
@@ -71,21 +170,79 @@ struct CheckOpt {
     ip: HostParam,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TopologyFormat {
+    Dot,
+    Mermaid,
+}
+
+impl FromStr for TopologyFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(TopologyFormat::Dot),
+            "mermaid" => Ok(TopologyFormat::Mermaid),
+            _ => bail!("unknown format {} (dot or mermaid)", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug, StructOpt)]
+struct TopologyOpt {
+    /// The output format: dot or mermaid
+    #[structopt(short = "-f", long = "--format", default_value = "dot")]
+    format: TopologyFormat,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+struct UsageOpt {
+    /// The usage percentage that triggers a warning and a non-zero exit code
+    #[structopt(short = "-w", long = "--warning", default_value = "90")]
+    warning: u8,
+    /// The minimum amount of free addresses, below which a warning is triggered
+    #[structopt(short = "-m", long = "--min-free")]
+    min_free: Option<u128>,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+struct WhoisOpt {
+    /// An IP address or a pot name; all the addresses of an alias pot are shown
+    target: String,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+struct ImportOpt {
+    /// Only check the document, reporting the conflicts with the pots of this host
+    #[structopt(short = "-c", long = "--check")]
+    check: bool,
+    /// The document to import, - for the standard input
+    file: PathBuf,
+}
+
 #[derive(Clone, Debug, StructOpt)]
 struct NewNetOpt {
     /// The number of host to be included in the network (gateway excluded)
-    #[structopt(short = "-s")]
-    host_number: u16,
+    #[structopt(short = "-s", required_unless = "list")]
+    host_number: Option<u16>,
+    /// List all the free blocks of the pot network, instead of providing a new network
+    #[structopt(short = "-l", long = "--list")]
+    list: bool,
+    /// Where to place the new network: lowest, highest or best-fit (smallest free block)
+    #[structopt(short = "-p", long = "--placement", default_value = "lowest")]
+    placement: Placement,
 }
 
-fn show(opt: &Opt, conf: &PotSystemConfig, ip_db: &mut BTreeMap<IpAddr, Option<String>>) {
+fn show(opt: &Opt, conf: &PotSystemConfig, space: &AddressSpace) {
     println!("Network topology:");
     println!("\tnetwork : {}", conf.network.trunc());
     println!("\tmin addr: {}", conf.network.network());
     println!("\tmax addr: {}", conf.network.broadcast());
     println!("\nAddresses already taken:");
-    for (ip, opt_name) in ip_db.iter() {
-        println!("\t{}\t{}", ip, opt_name.as_ref().unwrap_or(&"".to_string()));
+    for (ip, role) in space.iter() {
+        println!("\t{}\t{}", ip, role);
+    }
+    for (network, role) in space.iter_ranges() {
+        println!("\t{}\t{}", network, role);
     }
     if opt.verbose.get_level_filter() > log::LevelFilter::Warn {
         println!("\nDebug information\n{:#?}", conf);
@@ -93,114 +250,289 @@ fn show(opt: &Opt, conf: &PotSystemConfig, ip_db: &mut BTreeMap<IpAddr, Option<S
 }
 
 fn show_bridge(_opt: &Opt, conf: &PotSystemConfig, bridge_name: &str) -> Result<()> {
-    let bridges_list = get_bridges_list(conf)?;
-    if let Some(bridge) = bridges_list.iter().find(|x| x.name == bridge_name) {
-        info!("bridge {} found", bridge.name);
-        let mut ip_db = BTreeMap::new();
-        init_bridge_ipdb(bridge, conf, &mut ip_db);
-        for (ip, opt_name) in ip_db.iter() {
-            println!(
-                "\t{}\t{}",
-                ip,
-                match opt_name {
-                    Some(s) => s,
-                    None => "",
-                }
-            );
-        }
-    } else {
-        error!("bridge {} not found", bridge_name);
+    let space = AddressSpace::from_bridge(conf, bridge_name)?;
+    info!("bridge {} found", bridge_name);
+    for (ip, role) in space.iter() {
+        println!("\t{}\t{}", ip, role);
     }
     Ok(())
 }
 
-fn get(opt: &Opt, conf: &PotSystemConfig, ip_db: &BTreeMap<IpAddr, Option<String>>) {
-    for addr in conf.network.hosts() {
-        if !ip_db.contains_key(&addr) {
-            if opt.verbose.get_level_filter() > log::LevelFilter::Warn {
-                println!("{} available", addr);
+/// The host addresses of a network, accessible by index
+#[derive(Clone, Copy, Debug)]
+struct HostRange {
+    first: IpAddr,
+    last_index: u128,
+}
+
+impl HostRange {
+    fn new(network: &IpNet) -> Option<HostRange> {
+        let mut hosts = network.hosts();
+        let first = hosts.next()?;
+        let last = hosts.next_back().unwrap_or(first);
+        Some(HostRange {
+            first,
+            last_index: ip_to_u128(&last) - ip_to_u128(&first),
+        })
+    }
+
+    fn get(&self, index: u128) -> IpAddr {
+        match self.first {
+            V4(first) => V4(Ipv4Addr::from(u32::from(first) + index as u32)),
+            V6(first) => V6(Ipv6Addr::from(u128::from(first) + index)),
+        }
+    }
+
+    /// Iterate over all hosts, starting from the seed (modulo the size) and wrapping around
+    fn iter_from(self, seed: u128) -> impl Iterator<Item = IpAddr> {
+        // a size of zero means 2^128 hosts
+        let size = self.last_index.wrapping_add(1);
+        let start = if size == 0 { seed } else { seed % size };
+        (0..=self.last_index).map(move |i| {
+            let index = start.wrapping_add(i);
+            if size != 0 && (index >= size || index < start) {
+                self.get(index.wrapping_sub(size))
             } else {
-                println!("{}", addr);
+                self.get(index)
             }
-            break;
-        } else if opt.verbose.get_level_filter() > log::LevelFilter::Warn {
-            println!("{} already used", addr);
-        }
+        })
     }
 }
 
-fn get_network_size(host_number: u16) -> Option<u8> {
-    if host_number == 0 {
-        return None;
+fn ip_to_u128(ip: &IpAddr) -> u128 {
+    match ip {
+        V4(ip) => u128::from(u32::from(*ip)),
+        V6(ip) => u128::from(*ip),
     }
-    let mut max_hosts = 4u16;
-    let mut result = 2;
-    loop {
-        if host_number <= max_hosts - 2 {
-            break;
-        }
-        max_hosts <<= 1;
-        result += 1;
+}
+
+fn get_random_seed() -> u128 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    let high = RandomState::new().build_hasher().finish();
+    let low = RandomState::new().build_hasher().finish();
+    (u128::from(high) << 64) | u128::from(low)
+}
+
+/// FNV-1a hash of the pot name, stable across runs and Rust versions
+fn get_sticky_seed(pot_name: &str) -> u128 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in pot_name.bytes() {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
     }
-    Some(result)
+    u128::from(hash)
 }
 
-fn get_prefix_length(host_number: u16, ip_addr: &IpAddr) -> Option<u8> {
-    get_network_size(host_number).map(|network_size| match ip_addr {
-        V4(_) => 32,
-        V6(_) => 128,
-    } - network_size)
+/// All the host addresses of the network, in the order preferred by the strategy
+fn get_ordered_hosts(
+    network: &IpNet,
+    strategy: Strategy,
+    pot_name: Option<&str>,
+) -> Box<dyn Iterator<Item = IpAddr>> {
+    let range = match HostRange::new(network) {
+        Some(range) => range,
+        None => return Box::new(std::iter::empty()),
+    };
+    match strategy {
+        Strategy::Lowest | Strategy::SkipRecent => Box::new(range.iter_from(0)),
+        Strategy::Highest => Box::new((0..=range.last_index).rev().map(move |i| range.get(i))),
+        Strategy::Random => Box::new(range.iter_from(get_random_seed())),
+        Strategy::Sticky => Box::new(range.iter_from(get_sticky_seed(pot_name.unwrap_or("")))),
+    }
 }
 
-fn is_subnet_usable(subnet: IpNet, ip_db: &BTreeMap<IpAddr, Option<String>>) -> bool {
-    for ip in ip_db.keys() {
-        if subnet.contains(ip) {
-            return false;
+/// Track the addresses released by pots since the previous invocation
+///
+/// An address is released if it was allocated in the previous snapshot and it's not anymore;
+/// released addresses are forgotten when they are allocated again or the quarantine is over
+fn update_released(
+    previous: &BTreeMap<IpAddr, String>,
+    current: &BTreeMap<IpAddr, String>,
+    released: &mut BTreeMap<IpAddr, u64>,
+    now: u64,
+    quarantine: u64,
+) {
+    for (ip, pot_name) in previous {
+        if current.get(ip) != Some(pot_name) {
+            released.entry(*ip).or_insert(now);
         }
     }
-    true
+    released.retain(|ip, time| !current.contains_key(ip) && now.saturating_sub(*time) < quarantine);
 }
 
-fn new_net(host_number: u16, conf: &PotSystemConfig, ip_db: &BTreeMap<IpAddr, Option<String>>) {
-    if let Some(prefix_length) = get_prefix_length(host_number, &conf.gateway) {
-        info!("Subnet prefix length {}", prefix_length);
-        if let Ok(subnets) = conf.network.subnets(prefix_length) {
-            //info!("{} subnets to evaluate", subnets.count());
-            for s in subnets {
-                if is_subnet_usable(s, ip_db) {
-                    println!("net={}", s);
-                    println!("gateway={}", s.hosts().next().unwrap());
-                    break;
-                } else {
-                    debug!("{} not usable", s);
+fn get_potnet_state_dir(conf: &PotSystemConfig) -> PathBuf {
+    Path::new(&conf.fs_root).join("potnet")
+}
+
+fn read_state_file(path: &Path) -> BTreeMap<IpAddr, String> {
+    let mut result = BTreeMap::new();
+    if let Ok(content) = std::fs::read_to_string(path) {
+        for line in content.lines() {
+            let mut fields = line.split_whitespace();
+            if let (Some(ip), Some(value)) = (fields.next(), fields.next()) {
+                if let Ok(ip) = ip.parse() {
+                    result.insert(ip, value.to_string());
                 }
             }
         }
     }
+    result
 }
 
-fn get_next_from_bridge(opt: &Opt, conf: &PotSystemConfig, bridge_name: &str) -> Result<()> {
-    let bridges_list = get_bridges_list(conf)?;
-    if let Some(bridge) = bridges_list.iter().find(|x| x.name == bridge_name) {
-        info!("bridge {} found", bridge.name);
-        let mut ip_db = BTreeMap::new();
-        init_bridge_ipdb(bridge, conf, &mut ip_db);
-        for addr in bridge.network.hosts() {
-            if !ip_db.contains_key(&addr) {
-                if opt.verbose.get_level_filter() > log::LevelFilter::Warn {
-                    println!("{} available", addr);
-                } else {
-                    println!("{}", addr);
+fn write_state_file(path: &Path, content: &BTreeMap<IpAddr, String>) -> Result<()> {
+    let mut result = String::new();
+    for (ip, value) in content {
+        result.push_str(&format!("{} {}\n", ip, value));
+    }
+    std::fs::write(path, result)?;
+    Ok(())
+}
+
+/// Return the addresses released less than quarantine seconds ago
+///
+/// The state is updated only by the skip-recent strategy: a release is detected
+/// the first time potnet runs after the pot is gone
+fn get_recently_released(conf: &PotSystemConfig, quarantine: u64) -> Result<BTreeSet<IpAddr>> {
+    let state_dir = get_potnet_state_dir(conf);
+    std::fs::create_dir_all(&state_dir)?;
+    let allocated_path = state_dir.join("allocated");
+    let released_path = state_dir.join("released");
+    let previous = read_state_file(&allocated_path);
+    let mut released: BTreeMap<IpAddr, u64> = read_state_file(&released_path)
+        .into_iter()
+        .filter_map(|(ip, time)| time.parse().ok().map(|time| (ip, time)))
+        .collect();
+    let mut current = BTreeMap::new();
+    for v in &get_pot_conf_list(conf.clone()) {
+        if v.network_type == NetType::PublicBridge || v.network_type == NetType::PrivateBridge {
+            if let Some(ip) = v.ip_addr {
+                current.insert(ip, v.name.clone());
+            }
+        }
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    update_released(&previous, &current, &mut released, now, quarantine);
+    write_state_file(&allocated_path, &current)?;
+    write_state_file(
+        &released_path,
+        &released
+            .iter()
+            .map(|(ip, time)| (*ip, time.to_string()))
+            .collect(),
+    )?;
+    Ok(released.into_keys().collect())
+}
+
+/// Pick count free addresses, in the order preferred by the strategy
+///
+/// If contiguous is set, the addresses have to be consecutive
+fn select_addresses(
+    ordered_hosts: impl Iterator<Item = IpAddr>,
+    space: &AddressSpace,
+    skipped: &BTreeSet<IpAddr>,
+    count: usize,
+    contiguous: bool,
+) -> Vec<IpAddr> {
+    let mut result: Vec<IpAddr> = Vec::new();
+    for addr in ordered_hosts {
+        if let Err(e) = space.validate(&addr) {
+            debug!("{}", e);
+        } else if skipped.contains(&addr) {
+            debug!("{} recently released", addr);
+        } else {
+            if contiguous {
+                if let Some(last) = result.last() {
+                    if ip_to_u128(last).abs_diff(ip_to_u128(&addr)) != 1 {
+                        result.clear();
+                    }
                 }
+            }
+            result.push(addr);
+            if result.len() == count {
                 break;
             }
+            continue;
+        }
+        if contiguous {
+            result.clear();
         }
+    }
+    result
+}
+
+fn get_next_address(
+    opt: &Opt,
+    nopt: &NextOpt,
+    conf: &PotSystemConfig,
+    space: &AddressSpace,
+) -> Result<()> {
+    if nopt.count == 0 {
+        bail!("At least one address has to be requested");
+    }
+    let skipped = if nopt.strategy == Strategy::SkipRecent {
+        get_recently_released(conf, nopt.quarantine)?
     } else {
-        error!("bridge {} not found", bridge_name);
+        BTreeSet::new()
+    };
+    let ordered_hosts =
+        || get_ordered_hosts(&space.network, nopt.strategy, nopt.pot_name.as_deref());
+    let mut found = select_addresses(
+        ordered_hosts(),
+        space,
+        &skipped,
+        nopt.count,
+        nopt.contiguous,
+    );
+    if found.len() < nopt.count && !skipped.is_empty() {
+        warn!("not enough addresses available, recently released addresses are used");
+        found = select_addresses(
+            ordered_hosts(),
+            space,
+            &BTreeSet::new(),
+            nopt.count,
+            nopt.contiguous,
+        );
+    }
+    if found.len() < nopt.count {
+        bail!("Not enough addresses available in {}", space.network);
+    }
+    if nopt.contiguous {
+        found.sort();
+    }
+    let verbose = opt.verbose.get_level_filter() > log::LevelFilter::Warn;
+    for addr in found {
+        if verbose {
+            println!("{} available", addr);
+        } else {
+            println!("{}", addr);
+        }
     }
     Ok(())
 }
 
+fn new_net(host_number: u16, placement: Placement, space: &AddressSpace) {
+    debug!("free blocks: {:?}", space.get_free_blocks());
+    if let Some(s) = space.new_net(host_number, placement) {
+        info!("Subnet prefix length {}", s.prefix_len());
+        println!("net={}", s);
+        println!("gateway={}", s.hosts().next().unwrap());
+    }
+}
+
+fn get_next_from_bridge(
+    opt: &Opt,
+    nopt: &NextOpt,
+    conf: &PotSystemConfig,
+    bridge_name: &str,
+) -> Result<()> {
+    let space = AddressSpace::from_bridge_for(conf, bridge_name, nopt.for_pot.as_deref())?;
+    info!("bridge {} found", bridge_name);
+    get_next_address(opt, nopt, conf, &space)
+}
+
 fn get_hosts_from_bridge(_opt: &Opt, conf: &PotSystemConfig, bridge_name: &str) -> Result<()> {
     let bridges_list = get_bridges_list(conf)?;
     if let Some(bridge) = bridges_list.iter().find(|x| x.name == bridge_name) {
@@ -233,147 +565,789 @@ fn get_hosts_for_public_bridge(_opt: &Opt, conf: &PotSystemConfig) {
     }
 }
 
-fn validate_with_bridge(conf: &PotSystemConfig, bridge_name: &str, ip: IpAddr) -> Result<()> {
-    let bridges_list = get_bridges_list(conf)?;
-    if let Some(bridge) = bridges_list.iter().find(|x| x.name == bridge_name) {
-        info!("bridge {} found", bridge.name);
-        let mut ip_db = BTreeMap::new();
-        init_bridge_ipdb(bridge, conf, &mut ip_db);
-        // the ip address is in the bridge network
-        if !bridge.network.contains(&ip) {
-            error!("ip {} not in the bridge network {}", ip, bridge.network);
-            bail!("Ip outside the bridge network");
+/// Who is using an address
+fn describe_role(role: &AddressRole) -> String {
+    match role {
+        AddressRole::Network => "the network address".to_string(),
+        AddressRole::Broadcast => "the broadcast address".to_string(),
+        AddressRole::Gateway => "the default gateway".to_string(),
+        AddressRole::Dns(name) => format!("the DNS pot {}", name),
+        AddressRole::Pot(name) => format!("the pot {}", name),
+        AddressRole::BridgeNetwork(name) => format!("the network address of the bridge {}", name),
+        AddressRole::BridgeBroadcast(name) => {
+            format!("the broadcast address of the bridge {}", name)
+        }
+        AddressRole::BridgeGateway(name) => format!("the gateway of the bridge {}", name),
+        AddressRole::BridgeAllocated(name) => format!("the network of the bridge {}", name),
+        AddressRole::Reserved(description) => format!("a manual reservation ({})", description),
+    }
+}
+
+/// Explain why an address is valid or not, suggesting the nearest free address
+fn explain(space: &AddressSpace, ip: &IpAddr) -> Vec<String> {
+    let mut result = Vec::new();
+    match space.validate(ip) {
+        Ok(()) => {
+            result.push(format!("{} is available in {}", ip, space.network));
+            return result;
         }
-        // the ip is already in use
-        if ip_db.contains_key(&ip) {
-            error!("ip {} already in use", ip);
-            bail!("Ip already used");
+        Err(IpamError::AlreadyInUse(_, role)) => {
+            result.push(format!("{} is used by {}", ip, describe_role(&role)));
         }
-    } else {
-        bail!("bridge {} not found", bridge_name);
+        Err(IpamError::ForbiddenNetwork(_, network)) => {
+            result.push(format!(
+                "{} is inside the forbidden network {}",
+                ip, network
+            ));
+        }
+        Err(e) => result.push(e.to_string()),
     }
-    Ok(())
+    match space.get_nearest_free(ip) {
+        Some(nearest) => result.push(format!("nearest free address: {}", nearest)),
+        None => result.push(format!("no free address in {}", space.network)),
+    }
+    result
+}
+
+/// The addresses of the alias pots, by pot name
+fn get_alias_pots(conf: &PotSystemConfig) -> BTreeMap<String, Vec<IpAddr>> {
+    get_pot_list(conf)
+        .into_iter()
+        .filter_map(|pot_name| {
+            let aliases = get_pot_aliases(conf, &pot_name).ok()?;
+            (!aliases.addresses.is_empty()).then_some((pot_name, aliases.addresses))
+        })
+        .collect()
 }
 
-fn validate(
-    ip: IpAddr,
+/// Who is using the address: inside a bridge, the owner is looked up in the bridge
+fn whois_address(
     conf: &PotSystemConfig,
-    ip_db: &BTreeMap<IpAddr, Option<String>>,
-) -> Result<()> {
-    if ip_db.contains_key(&ip) {
-        bail!("Address already in use");
+    bridges: &[BridgeConf],
+    pots: &[PotConf],
+    aliases: &BTreeMap<String, Vec<IpAddr>>,
+    reservations: &BTreeMap<IpAddr, String>,
+    ip: &IpAddr,
+) -> String {
+    if let Some((pot_name, _)) = aliases.iter().find(|(_, addresses)| addresses.contains(ip)) {
+        return format!("{}: the alias pot {}", ip, pot_name);
+    }
+    if let Some(bridge) = bridges.iter().find(|x| x.network.contains(ip)) {
+        let mut space = AddressSpace::with_bridge_pots(conf, bridge, pots);
+        space.add_reservations(reservations);
+        return match space.get_owner(ip) {
+            Some(role) => format!("{}: {} (bridge {})", ip, describe_role(role), bridge.name),
+            None => format!("{}: free (bridge {} {})", ip, bridge.name, bridge.network),
+        };
+    }
+    let mut space = AddressSpace::with_pots(conf, bridges, pots);
+    space.add_reservations(reservations);
+    match space.validate(ip) {
+        Ok(()) => format!("{}: free ({})", ip, space.network),
+        Err(IpamError::AlreadyInUse(_, role)) => format!("{}: {}", ip, describe_role(&role)),
+        Err(e) => format!("{}: {}", ip, e),
+    }
+}
+
+fn net_type_to_str(net_type: &NetType) -> &'static str {
+    match net_type {
+        NetType::Inherit => "inherit",
+        NetType::Alias => "alias",
+        NetType::PublicBridge => "public-bridge",
+        NetType::PrivateBridge => "private-bridge",
+    }
+}
+
+/// The network configuration and the state of a pot, if it's known
+///
+/// All the addresses of an alias pot are listed, a bridge pot has only one
+fn whois_pot(
+    conf: &PotSystemConfig,
+    bridges: &[BridgeConf],
+    pots: &[PotConf],
+    aliases: &BTreeMap<String, Vec<IpAddr>>,
+    running: &[String],
+    pot_name: &str,
+) -> Option<Vec<String>> {
+    let (network_type, addresses) = match pots.iter().find(|x| x.name == pot_name) {
+        Some(pot) => (&pot.network_type, pot.ip_addr.into_iter().collect()),
+        None => (&NetType::Alias, aliases.get(pot_name)?.clone()),
+    };
+    let mut result = vec![
+        format!("pot {}", pot_name),
+        format!("\tnetwork type: {}", net_type_to_str(network_type)),
+    ];
+    for ip in addresses {
+        result.push(format!("\taddress: {}", ip));
+        if *network_type == NetType::PrivateBridge {
+            if let Some(bridge) = bridges.iter().find(|x| x.network.contains(&ip)) {
+                result.push(format!("\tbridge: {} ({})", bridge.name, bridge.network));
+            }
+        }
     }
-    if !conf.network.contains(&ip) {
-        bail!("Address outside the network");
+    if let Some(dns) = conf.dns.as_ref().filter(|x| x.pot_name == pot_name) {
+        result.push(format!("\tDNS pot: {}", dns.ip));
+    }
+    let is_running = running.iter().any(|x| x == pot_name);
+    result.push(format!(
+        "\trunning: {}",
+        if is_running { "yes" } else { "no" }
+    ));
+    Some(result)
+}
+
+fn whois(conf: &PotSystemConfig, target: &str) -> Result<()> {
+    let bridges = get_bridges_list(conf)?;
+    let pots = get_pot_conf_list(conf.clone());
+    let aliases = get_alias_pots(conf);
+    if let Ok(ip) = target.parse::<IpAddr>() {
+        let reservations = get_reservations(conf)?;
+        println!(
+            "{}",
+            whois_address(conf, &bridges, &pots, &aliases, &reservations, &ip)
+        );
+        return Ok(());
+    }
+    let running = get_running_pot_list(conf);
+    match whois_pot(conf, &bridges, &pots, &aliases, &running, target) {
+        Some(lines) => {
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+        None => bail!("pot {} not found", target),
     }
     Ok(())
 }
 
-fn init_bridge_ipdb(
-    bridge: &BridgeConf,
+/// The version of the document created by export
+const EXPORT_VERSION: u32 = 1;
+
+/// The network state, as exported
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct NetworkDocument {
+    version: u32,
+    /// The pot system settings, like POT_NETWORK
+    settings: BTreeMap<String, String>,
+    network: IpNet,
+    gateway: IpAddr,
+    bridges: Vec<BridgeDocument>,
+    pots: Vec<PotDocument>,
+    reservations: BTreeMap<IpAddr, String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct BridgeDocument {
+    name: String,
+    network: IpNet,
+    gateway: IpAddr,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct PotDocument {
+    name: String,
+    network_type: String,
+    ip: Option<IpAddr>,
+    /// The private bridge of the pot
+    bridge: Option<String>,
+}
+
+fn export_document(
     conf: &PotSystemConfig,
-    ip_db: &mut BTreeMap<IpAddr, Option<String>>,
-) {
-    info!("Evaluating bridge {:?}", bridge);
-    // add the network address
-    let mut description = String::from(bridge.name.as_str());
-    description.push_str(" bridge - network ");
-    ip_db.insert(bridge.network.network(), Some(description));
-    // add the broadcast address
-    let mut description = String::from(bridge.name.as_str());
-    description.push_str(" bridge - broadcast ");
-    ip_db.insert(bridge.network.broadcast(), Some(description));
-    // add the broadcast address
-    let mut description = String::from(bridge.name.as_str());
-    description.push_str(" bridge - gateway ");
-    ip_db.insert(bridge.gateway, Some(description));
-    for v in &get_pot_conf_list(conf.clone()) {
-        if (v.network_type == NetType::PublicBridge || v.network_type == NetType::PrivateBridge)
-            && bridge.network.contains(&v.ip_addr.unwrap())
+    bridges: &[BridgeConf],
+    pots: &[PotConf],
+    reservations: &BTreeMap<IpAddr, String>,
+) -> NetworkDocument {
+    let mut pots: Vec<PotDocument> = pots
+        .iter()
+        .map(|pot| PotDocument {
+            name: pot.name.clone(),
+            network_type: net_type_to_str(&pot.network_type).to_string(),
+            ip: pot.ip_addr,
+            bridge: pot
+                .ip_addr
+                .filter(|_| pot.network_type == NetType::PrivateBridge)
+                .and_then(|ip| bridges.iter().find(|b| b.network.contains(&ip)))
+                .map(|b| b.name.clone()),
+        })
+        .collect();
+    pots.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut bridges: Vec<BridgeDocument> = bridges
+        .iter()
+        .map(|b| BridgeDocument {
+            name: b.name.clone(),
+            network: b.network,
+            gateway: b.gateway,
+        })
+        .collect();
+    bridges.sort_by(|a, b| a.name.cmp(&b.name));
+    NetworkDocument {
+        version: EXPORT_VERSION,
+        settings: conf
+            .provenance
+            .values
+            .iter()
+            .map(|(key, value)| (key.clone(), value.value.clone()))
+            .collect(),
+        network: conf.network,
+        gateway: conf.gateway,
+        bridges,
+        pots,
+        reservations: reservations.clone(),
+    }
+}
+
+/// The conflicts between the document and the pots, the bridges and the reservations of this host,
+/// and inside the document itself
+fn check_import(
+    doc: &NetworkDocument,
+    conf: &PotSystemConfig,
+    bridges: &[BridgeConf],
+    pots: &[PotConf],
+    reservations: &BTreeMap<IpAddr, String>,
+) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut space = AddressSpace::with_pots(conf, bridges, pots);
+    space.add_reservations(reservations);
+    // the accepted bridges and pots of the document are added, to check it against itself
+    let mut bridge_spaces: BTreeMap<String, AddressSpace> = bridges
+        .iter()
+        .map(|bridge| {
+            let mut bridge_space = AddressSpace::with_bridge_pots(conf, bridge, pots);
+            bridge_space.add_reservations(reservations);
+            (bridge.name.clone(), bridge_space)
+        })
+        .collect();
+    for bridge in &doc.bridges {
+        if !bridge.network.contains(&bridge.gateway)
+            || bridge.network.network() == bridge.gateway
+            || bridge.network.broadcast() == bridge.gateway
         {
-            ip_db.insert(v.ip_addr.unwrap(), Some(v.name.clone()));
+            result.push(format!(
+                "bridge {}: gateway {} not a host of the network {}",
+                bridge.name, bridge.gateway, bridge.network
+            ));
+            continue;
+        }
+        match bridges.iter().find(|x| x.name == bridge.name) {
+            Some(local) if local.network != bridge.network => result.push(format!(
+                "bridge {}: network {}, but {} on this host",
+                bridge.name, bridge.network, local.network
+            )),
+            Some(local) if local.gateway != bridge.gateway => result.push(format!(
+                "bridge {}: gateway {}, but {} on this host",
+                bridge.name, bridge.gateway, local.gateway
+            )),
+            Some(_) => (),
+            None if !space.is_subnet_usable(&bridge.network) => result.push(format!(
+                "bridge {}: network {} not available on this host",
+                bridge.name, bridge.network
+            )),
+            None => {
+                let bridge = BridgeConf {
+                    name: bridge.name.clone(),
+                    network: bridge.network,
+                    gateway: bridge.gateway,
+                };
+                space.add_bridge(&bridge);
+                bridge_spaces.insert(
+                    bridge.name.clone(),
+                    AddressSpace::with_bridge_pots(conf, &bridge, &[]),
+                );
+            }
+        }
+    }
+    for pot in &doc.pots {
+        if pots.iter().any(|x| x.name == pot.name) {
+            result.push(format!("pot {}: already present on this host", pot.name));
+            continue;
+        }
+        // only the bridge pots take their address from the pot network
+        let (Some(ip), "public-bridge" | "private-bridge") = (pot.ip, pot.network_type.as_str())
+        else {
+            continue;
+        };
+        let pot_space = match &pot.bridge {
+            Some(name) => match bridge_spaces.get_mut(name) {
+                Some(bridge_space) => bridge_space,
+                // the bridge has been rejected, already reported
+                None => continue,
+            },
+            None => &mut space,
+        };
+        match pot_space.validate(&ip) {
+            Ok(()) => pot_space.insert(ip, AddressRole::Pot(pot.name.clone())),
+            Err(e) => result.push(format!("pot {}: {}", pot.name, e)),
+        }
+    }
+    for (ip, description) in &doc.reservations {
+        // the addresses of the bridges are owned by their own space
+        let owner_space = bridge_spaces
+            .values()
+            .find(|x| x.network.contains(ip))
+            .unwrap_or(&space);
+        if owner_space.network.contains(ip) {
+            if let Some(role) = owner_space.get_owner(ip) {
+                if role != &AddressRole::Reserved(description.clone()) {
+                    result.push(format!(
+                        "reservation {} ({}): used by {}",
+                        ip,
+                        description,
+                        describe_role(role)
+                    ));
+                }
+            }
+        }
+    }
+    result
+}
+
+fn export(conf: &PotSystemConfig) -> Result<()> {
+    let bridges = get_bridges_list(conf)?;
+    let pots = get_pot_conf_list(conf.clone());
+    let reservations = get_reservations(conf).unwrap_or_else(|e| {
+        warn!("reservations not exported: {}", e);
+        BTreeMap::new()
+    });
+    let doc = export_document(conf, &bridges, &pots, &reservations);
+    println!("{}", serde_json::to_string_pretty(&doc)?);
+    Ok(())
+}
+
+fn read_document(path: &Path) -> Result<NetworkDocument> {
+    let content = if path == Path::new("-") {
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut content)?;
+        content
+    } else {
+        std::fs::read_to_string(path)?
+    };
+    let doc: NetworkDocument = serde_json::from_str(&content)?;
+    if doc.version != EXPORT_VERSION {
+        bail!(
+            "unsupported document version {} (expected {})",
+            doc.version,
+            EXPORT_VERSION
+        );
+    }
+    Ok(doc)
+}
+
+/// Check the document; the return value is true if conflicts are found
+fn import(conf: &PotSystemConfig, opt: &ImportOpt) -> Result<bool> {
+    if !opt.check {
+        bail!("only the check of the document is supported, use --check");
+    }
+    let doc = read_document(&opt.file)?;
+    let bridges = get_bridges_list(conf)?;
+    let pots = get_pot_conf_list(conf.clone());
+    let reservations = get_reservations(conf)?;
+    let conflicts = check_import(&doc, conf, &bridges, &pots, &reservations);
+    for conflict in &conflicts {
+        println!("{}", conflict);
+    }
+    info!(
+        "{} pots checked, {} conflicts",
+        doc.pots.len(),
+        conflicts.len()
+    );
+    Ok(!conflicts.is_empty())
+}
+
+/// The addresses of the host owned by pot: the gateways of the pot network and of the bridges
+fn get_pot_addresses(conf: &PotSystemConfig) -> Vec<IpAddr> {
+    let mut result = vec![conf.gateway];
+    if let Ok(bridges) = get_bridges_list(conf) {
+        result.extend(bridges.iter().map(|b| b.gateway));
+    }
+    result
+}
+
+/// The host interfaces and routes overlapping the networks, if they can be read
+///
+/// The host networks are read once, every network is checked against the same snapshot
+fn get_host_conflicts(conf: &PotSystemConfig, networks: &[IpNet]) -> Vec<HostConflict> {
+    let host_networks = match HostNetworks::from_system(&SystemRunner) {
+        Ok(host_networks) => host_networks,
+        Err(e) => {
+            warn!("unable to read the host networks: {}", e);
+            return Vec::new();
+        }
+    };
+    let pot_addresses = get_pot_addresses(conf);
+    let mut result: Vec<HostConflict> = Vec::new();
+    for network in networks {
+        for conflict in host_networks.get_conflicts(network, &pot_addresses) {
+            if !result.contains(&conflict) {
+                result.push(conflict);
+            }
+        }
+    }
+    result
+}
+
+/// Forbid the networks already used by the host, so new-net doesn't use them
+fn add_host_networks(conf: &PotSystemConfig, space: &mut AddressSpace) {
+    for conflict in get_host_conflicts(conf, &[conf.network]) {
+        debug!(
+            "{} used by the host: {}",
+            conflict.network, conflict.description
+        );
+        space.add_forbidden(conflict.network);
+    }
+}
+
+/// The VPN networks overlapping the pot network
+fn get_vpn_overlaps(conf: &PotSystemConfig) -> Vec<IpNet> {
+    conf.vpn_networks
+        .iter()
+        .filter(|x| is_overlapping(x, &conf.network))
+        .copied()
+        .collect()
+}
+
+/// The address utilization of a network
+#[derive(Debug, PartialEq, Eq)]
+struct NetworkUsage {
+    name: String,
+    network: IpNet,
+    total: u128,
+    reserved: u128,
+    allocated: u128,
+    bridges: u128,
+    free: u128,
+}
+
+impl NetworkUsage {
+    fn used_percent(&self) -> f64 {
+        if self.total == 0 {
+            return 100.0;
+        }
+        (self.total - self.free) as f64 * 100.0 / self.total as f64
+    }
+
+    fn is_exceeding(&self, opt: &UsageOpt) -> bool {
+        if self.used_percent() >= f64::from(opt.warning) {
+            return true;
+        }
+        match opt.min_free {
+            Some(min_free) => self.free < min_free,
+            None => false,
         }
     }
 }
 
-fn init_ipdb(conf: &PotSystemConfig, ip_db: &mut BTreeMap<IpAddr, Option<String>>) -> Result<()> {
-    info!("Insert network {:?}", conf.network);
-    ip_db.insert(conf.network.network(), None);
-    info!("Insert broadcast {:?}", conf.network);
-    ip_db.insert(conf.network.broadcast(), None);
-    info!("Insert gateway {:?}", conf.gateway);
-    ip_db.insert(conf.gateway, Some("default gateway".to_string()));
+fn get_network_addresses(network: &IpNet) -> u128 {
+    1u128
+        .checked_shl(u32::from(network.max_prefix_len() - network.prefix_len()))
+        .unwrap_or(u128::MAX)
+}
+
+fn get_network_usage(
+    conf: &PotSystemConfig,
+    bridges: &[BridgeConf],
+    pots: &[PotConf],
+) -> NetworkUsage {
+    let bridges: Vec<&BridgeConf> = bridges
+        .iter()
+        .filter(|b| conf.network.contains(&b.network))
+        .collect();
+    let in_bridge = |ip: &IpAddr| bridges.iter().any(|b| b.network.contains(ip));
+    let mut reserved = BTreeSet::new();
+    reserved.insert(conf.network.network());
+    reserved.insert(conf.network.broadcast());
+    reserved.insert(conf.gateway);
     if let Some(dns) = &conf.dns {
-        info!("Insert dns {:?}", dns.ip);
-        ip_db.insert(dns.ip, Some(dns.pot_name.clone()));
+        reserved.insert(dns.ip);
     }
-    for v in &get_pot_conf_list(conf.clone()) {
-        if v.network_type == NetType::PublicBridge || v.network_type == NetType::PrivateBridge {
-            info!("Insert pot {:?}", v.ip_addr.unwrap());
-            ip_db.insert(v.ip_addr.unwrap(), Some(v.name.clone()));
-        }
-    }
-    for b in &get_bridges_list(conf)? {
-        info!("Evaluating bridge {:?}", b);
-        // add the network address
-        let mut description = String::from(b.name.as_str());
-        description.push_str(" bridge - network ");
-        ip_db.insert(b.network.network(), Some(description));
-        // add the broadcast address
-        let mut description = String::from(b.name.as_str());
-        description.push_str(" bridge - broadcast ");
-        ip_db.insert(b.network.broadcast(), Some(description));
-        // add the broadcast address
-        let mut description = String::from(b.name.as_str());
-        description.push_str(" bridge - gateway ");
-        ip_db.insert(b.gateway, Some(description));
-        // add all the not yet allocated hosts
-        let mut description = String::from(b.name.as_str());
-        description.push_str(" bridge - allocated address");
-        for host in b.network.hosts() {
-            ip_db
-                .entry(host)
-                .or_insert_with(|| Some(description.clone()));
+    reserved.retain(|ip| conf.network.contains(ip) && !in_bridge(ip));
+    let allocated: BTreeSet<IpAddr> = pots
+        .iter()
+        .filter(|p| {
+            p.network_type == NetType::PublicBridge || p.network_type == NetType::PrivateBridge
+        })
+        .filter_map(|p| p.ip_addr)
+        .filter(|ip| conf.network.contains(ip) && !in_bridge(ip) && !reserved.contains(ip))
+        .collect();
+    let total = get_network_addresses(&conf.network);
+    let carved: u128 = bridges
+        .iter()
+        .map(|b| get_network_addresses(&b.network))
+        .sum();
+    let reserved = reserved.len() as u128;
+    let allocated = allocated.len() as u128;
+    NetworkUsage {
+        name: "POT_NETWORK".to_string(),
+        network: conf.network.trunc(),
+        total,
+        reserved,
+        allocated,
+        bridges: carved,
+        free: total.saturating_sub(reserved + allocated + carved),
+    }
+}
+
+fn get_bridge_usage(bridge: &BridgeConf, pots: &[PotConf]) -> NetworkUsage {
+    let mut reserved = BTreeSet::new();
+    reserved.insert(bridge.network.network());
+    reserved.insert(bridge.network.broadcast());
+    reserved.insert(bridge.gateway);
+    let allocated: BTreeSet<IpAddr> = pots
+        .iter()
+        .filter(|p| {
+            p.network_type == NetType::PublicBridge || p.network_type == NetType::PrivateBridge
+        })
+        .filter_map(|p| p.ip_addr)
+        .filter(|ip| bridge.network.contains(ip) && !reserved.contains(ip))
+        .collect();
+    let total = get_network_addresses(&bridge.network);
+    let reserved = reserved.len() as u128;
+    let allocated = allocated.len() as u128;
+    NetworkUsage {
+        name: format!("bridge {}", bridge.name),
+        network: bridge.network.trunc(),
+        total,
+        reserved,
+        allocated,
+        bridges: 0,
+        free: total.saturating_sub(reserved + allocated),
+    }
+}
+
+fn usage(conf: &PotSystemConfig, opt: &UsageOpt) -> Result<bool> {
+    let bridges = get_bridges_list(conf)?;
+    let pots = get_pot_conf_list(conf.clone());
+    let mut usages = vec![get_network_usage(conf, &bridges, &pots)];
+    for b in &bridges {
+        usages.push(get_bridge_usage(b, &pots));
+    }
+    let mut exceeded = false;
+    for u in &usages {
+        println!("{} {}", u.name, u.network);
+        println!("\ttotal    : {}", u.total);
+        println!("\treserved : {}", u.reserved);
+        println!("\tallocated: {}", u.allocated);
+        if u.bridges != 0 {
+            println!("\tbridges  : {}", u.bridges);
+        }
+        println!("\tfree     : {}", u.free);
+        println!("\tusage    : {:.1}%", u.used_percent());
+        if u.is_exceeding(opt) {
+            warn!(
+                "{} {} is running out of addresses ({} free)",
+                u.name, u.network, u.free
+            );
+            exceeded = true;
+        }
+    }
+    Ok(exceeded)
+}
+
+/// A node of the topology graph: the id is unique, the label is multi-line
+struct TopologyNode {
+    id: String,
+    label: Vec<String>,
+    is_network: bool,
+}
+
+/// Build the topology graph as a list of nodes and a list of edges (pairs of node ids)
+fn topology_graph(
+    conf: &PotSystemConfig,
+    bridges: &[BridgeConf],
+    pots: &[PotConf],
+) -> (Vec<TopologyNode>, Vec<(String, String)>) {
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+    nodes.push(TopologyNode {
+        id: "host".to_string(),
+        label: vec!["host".to_string(), conf.ext_if.clone()],
+        is_network: true,
+    });
+    nodes.push(TopologyNode {
+        id: "public_bridge".to_string(),
+        label: vec![
+            "public-bridge".to_string(),
+            conf.network.trunc().to_string(),
+            format!("gateway {}", conf.gateway),
+        ],
+        is_network: true,
+    });
+    edges.push(("host".to_string(), "public_bridge".to_string()));
+    for (i, bridge) in bridges.iter().enumerate() {
+        let id = format!("bridge{}", i);
+        nodes.push(TopologyNode {
+            id: id.clone(),
+            label: vec![
+                format!("{} bridge", bridge.name),
+                bridge.network.trunc().to_string(),
+                format!("gateway {}", bridge.gateway),
+            ],
+            is_network: true,
+        });
+        edges.push(("host".to_string(), id));
+    }
+    let mut sorted_pots: Vec<&PotConf> = pots.iter().collect();
+    sorted_pots.sort_by(|a, b| a.name.cmp(&b.name));
+    for (i, pot) in sorted_pots.iter().enumerate() {
+        let parent = match (&pot.network_type, pot.ip_addr) {
+            (NetType::Inherit, _) => "host".to_string(),
+            (NetType::PublicBridge, Some(_)) => "public_bridge".to_string(),
+            (NetType::PrivateBridge, Some(ip)) => {
+                match bridges.iter().position(|b| b.network.contains(&ip)) {
+                    Some(b) => format!("bridge{}", b),
+                    None => {
+                        debug!("pot {} not attached to any known bridge", pot.name);
+                        continue;
+                    }
+                }
+            }
+            _ => continue,
+        };
+        let id = format!("pot{}", i);
+        nodes.push(TopologyNode {
+            id: id.clone(),
+            label: vec![
+                pot.name.clone(),
+                match pot.ip_addr {
+                    Some(ip) => ip.to_string(),
+                    None => "inherit".to_string(),
+                },
+            ],
+            is_network: false,
+        });
+        edges.push((parent, id));
+    }
+    (nodes, edges)
+}
+
+fn topology_to_dot(nodes: &[TopologyNode], edges: &[(String, String)]) -> String {
+    let mut result = String::from("graph potnet {\n");
+    for node in nodes {
+        let label = node
+            .label
+            .iter()
+            .map(|x| x.replace('\\', "\\\\").replace('"', "\\\""))
+            .collect::<Vec<String>>()
+            .join("\\n");
+        let shape = if node.is_network { "box" } else { "ellipse" };
+        result.push_str(&format!(
+            "\t{} [label=\"{}\", shape={}];\n",
+            node.id, label, shape
+        ));
+    }
+    for (from, to) in edges {
+        result.push_str(&format!("\t{} -- {};\n", from, to));
+    }
+    result.push_str("}\n");
+    result
+}
+
+fn topology_to_mermaid(nodes: &[TopologyNode], edges: &[(String, String)]) -> String {
+    let mut result = String::from("graph TD\n");
+    for node in nodes {
+        let label = node
+            .label
+            .iter()
+            .map(|x| x.replace('"', "#quot;"))
+            .collect::<Vec<String>>()
+            .join("<br/>");
+        if node.is_network {
+            result.push_str(&format!("    {}[\"{}\"]\n", node.id, label));
+        } else {
+            result.push_str(&format!("    {}(\"{}\")\n", node.id, label));
         }
     }
+    for (from, to) in edges {
+        result.push_str(&format!("    {} --- {}\n", from, to));
+    }
+    result
+}
+
+fn topology(conf: &PotSystemConfig, format: TopologyFormat) -> Result<()> {
+    let bridges = get_bridges_list(conf)?;
+    let pots = get_pot_conf_list(conf.clone());
+    let (nodes, edges) = topology_graph(conf, &bridges, &pots);
+    match format {
+        TopologyFormat::Dot => print!("{}", topology_to_dot(&nodes, &edges)),
+        TopologyFormat::Mermaid => print!("{}", topology_to_mermaid(&nodes, &edges)),
+    }
     Ok(())
 }
 
+/// Render the effective configuration, with the origin of each setting and the values
+/// that have been discarded
+fn config_show(provenance: &ConfProvenance) -> String {
+    let mut result =
+        "# precedence: environment < pot.default.conf < pot.conf < command line\n".to_string();
+    for key in POT_SYSTEM_KEYS {
+        match provenance.values.get(*key) {
+            Some(value) => {
+                result.push_str(&format!("{}={} # {}\n", key, value.value, value.source))
+            }
+            None => result.push_str(&format!("# {} is not set\n", key)),
+        }
+    }
+    if !provenance.discarded.is_empty() {
+        result.push_str("# discarded values:\n");
+        for discarded in &provenance.discarded {
+            result.push_str(&format!(
+                "# {}={} from {}: {}\n",
+                discarded.key, discarded.value, discarded.source, discarded.reason
+            ));
+        }
+    }
+    result
+}
+
 fn main() -> Result<()> {
     let opt = Opt::from_args();
     opt.verbose.set_log_level();
     trace!("potnet start");
 
-    let conf = PotSystemConfig::from_system()?;
-    let mut ip_db = BTreeMap::new();
-    init_ipdb(&conf, &mut ip_db)?;
+    if let Command::ConfigShow = opt.subcommand {
+        // it has to work with an incomplete configuration too
+        print!("{}", config_show(&get_conf_provenance(&opt.set)));
+        return Ok(());
+    }
+    let conf = PotSystemConfig::from_system_with(&opt.set)?;
+    // the address space is built only by the subcommands using it: reading the pots and the
+    // reservations can fail, and the other subcommands are needed to diagnose it
     let opt_clone = opt.clone();
     match opt.subcommand {
         Command::Show(bopt) => {
             if let Some(bridge_name) = bopt.bridge_name {
                 show_bridge(&opt_clone, &conf, &bridge_name)?;
             } else {
-                show(&opt_clone, &conf, &mut ip_db);
+                show(&opt_clone, &conf, &AddressSpace::from_system(&conf)?);
             }
         }
         Command::Next(nopt) => {
-            if let Some(bridge_name) = nopt.bridge_name {
+            if let Some(bridge_name) = &nopt.bridge_name {
                 debug!("get an ip for the bridge {}", bridge_name);
-                get_next_from_bridge(&opt_clone, &conf, &bridge_name)?;
+                get_next_from_bridge(&opt_clone, &nopt, &conf, bridge_name)?;
             } else {
-                get(&opt_clone, &conf, &ip_db);
+                let space = AddressSpace::from_system_for(&conf, nopt.for_pot.as_deref())?;
+                get_next_address(&opt_clone, &nopt, &conf, &space)?;
             }
         }
         Command::Validate(vopt) => {
-            if let Some(bridge_name) = vopt.bridge_name {
-                debug!(
-                    "validate the ip {} for the bridge {}",
-                    &vopt.ip.host_addr, bridge_name
-                );
-                return validate_with_bridge(&conf, &bridge_name, vopt.ip.host_addr);
-            } else {
-                return validate(vopt.ip.host_addr, &conf, &ip_db);
+            let for_pot = vopt.for_pot.as_deref();
+            let space = match &vopt.bridge_name {
+                Some(bridge_name) => {
+                    debug!(
+                        "validate the ip {} for the bridge {}",
+                        &vopt.ip.host_addr, bridge_name
+                    );
+                    AddressSpace::from_bridge_for(&conf, bridge_name, for_pot)?
+                }
+                None => AddressSpace::from_system_for(&conf, for_pot)?,
+            };
+            if vopt.explain {
+                for line in explain(&space, &vopt.ip.host_addr) {
+                    println!("{}", line);
+                }
             }
+            space.validate(&vopt.ip.host_addr)?;
         }
         Command::IP4(x) => {
             if !x.ip.host_addr.is_ipv4() {
@@ -409,16 +1383,48 @@ fn main() -> Result<()> {
                     conf.netmask, conf.network
                 );
             }
-            if !conf.network.contains(&conf.gateway) || conf.network.netmask() != conf.netmask {
+            // the VPN networks are carved out of the pot network, as forbidden ranges
+            for vpn_network in get_vpn_overlaps(&conf) {
+                warn!(
+                    "VPN network ({}) overlapping the network range ({}), not used for pots",
+                    vpn_network, conf.network
+                );
+            }
+            if !conf.vpn_networks.is_empty() && conf.vpn_extif.is_none() {
+                warn!("VPN networks configured, but POT_VPN_EXTIF is not set");
+            }
+            let mut networks = vec![conf.network];
+            networks.extend(get_bridges_list(&conf)?.iter().map(|b| b.network));
+            let host_conflicts = get_host_conflicts(&conf, &networks);
+            for conflict in &host_conflicts {
+                error!(
+                    "host network ({}) overlapping the pot networks: {}",
+                    conflict.network, conflict.description
+                );
+            }
+            if !conf.network.contains(&conf.gateway)
+                || conf.network.netmask() != conf.netmask
+                || !host_conflicts.is_empty()
+            {
                 std::process::exit(1);
             }
         }
+        Command::ConfigShow => (),
         Command::NewNetwork(x) => {
-            if x.host_number <= 1 {
-                error!("A network with size {} is too small", x.host_number);
+            let mut space = AddressSpace::from_system(&conf)?;
+            add_host_networks(&conf, &mut space);
+            if x.list {
+                for block in space.get_free_blocks() {
+                    println!("{}", block);
+                }
+                return Ok(());
+            }
+            let host_number = x.host_number.unwrap_or_default();
+            if host_number <= 1 {
+                error!("A network with size {} is too small", host_number);
                 std::process::exit(1);
             }
-            new_net(x.host_number, &conf, &ip_db);
+            new_net(host_number, x.placement, &space);
         }
         Command::EtcHosts(ehopt) => {
             if let Some(bridge_name) = ehopt.bridge_name {
@@ -428,6 +1434,25 @@ fn main() -> Result<()> {
                 get_hosts_for_public_bridge(&opt_clone, &conf);
             }
         }
+        Command::Topology(topt) => {
+            topology(&conf, topt.format)?;
+        }
+        Command::Usage(uopt) => {
+            if usage(&conf, &uopt)? {
+                std::process::exit(1);
+            }
+        }
+        Command::Whois(wopt) => {
+            whois(&conf, &wopt.target)?;
+        }
+        Command::Export => {
+            export(&conf)?;
+        }
+        Command::Import(iopt) => {
+            if import(&conf, &iopt)? {
+                std::process::exit(1);
+            }
+        }
     }
     Ok(())
 }
@@ -435,58 +1460,558 @@ fn main() -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn topology_test_data() -> (PotSystemConfig, Vec<BridgeConf>, Vec<PotConf>) {
+        let conf = PotSystemConfig {
+            network: "10.192.0.0/24".parse().unwrap(),
+            gateway: "10.192.0.1".parse().unwrap(),
+            ext_if: "em0".to_string(),
+            ..Default::default()
+        };
+        let bridges = vec![BridgeConf {
+            name: "test-bridge".to_string(),
+            network: "10.192.0.16/29".parse().unwrap(),
+            gateway: "10.192.0.17".parse().unwrap(),
+        }];
+        let pots = vec![
+            PotConf {
+                name: "web1".to_string(),
+                ip_addr: Some("10.192.0.3".parse().unwrap()),
+                network_type: NetType::PublicBridge,
+            },
+            PotConf {
+                name: "db1".to_string(),
+                ip_addr: Some("10.192.0.18".parse().unwrap()),
+                network_type: NetType::PrivateBridge,
+            },
+            PotConf {
+                name: "lonely".to_string(),
+                ip_addr: Some("10.192.0.130".parse().unwrap()),
+                network_type: NetType::PrivateBridge,
+            },
+        ];
+        (conf, bridges, pots)
+    }
+
+    #[test]
+    fn topology_to_dot_001() {
+        let (conf, bridges, pots) = topology_test_data();
+        let (nodes, edges) = topology_graph(&conf, &bridges, &pots);
+        let uut = topology_to_dot(&nodes, &edges);
+        assert!(uut.starts_with("graph potnet {\n"));
+        assert!(uut.contains("\thost [label=\"host\\nem0\", shape=box];\n"));
+        assert!(uut.contains(
+            "\tbridge0 [label=\"test-bridge bridge\\n10.192.0.16/29\\ngateway 10.192.0.17\", shape=box];\n"
+        ));
+        // pots are sorted by name: db1, lonely, web1
+        assert!(uut.contains("\tpot0 [label=\"db1\\n10.192.0.18\", shape=ellipse];\n"));
+        assert!(uut.contains("\tbridge0 -- pot0;\n"));
+        assert!(uut.contains("\tpublic_bridge -- pot2;\n"));
+        assert!(!uut.contains("lonely"));
+        assert!(uut.ends_with("}\n"));
+    }
+
+    #[test]
+    fn topology_to_mermaid_001() {
+        let (conf, bridges, pots) = topology_test_data();
+        let (nodes, edges) = topology_graph(&conf, &bridges, &pots);
+        let uut = topology_to_mermaid(&nodes, &edges);
+        assert!(uut.starts_with("graph TD\n"));
+        assert!(uut.contains(
+            "    public_bridge[\"public-bridge<br/>10.192.0.0/24<br/>gateway 10.192.0.1\"]\n"
+        ));
+        assert!(uut.contains("    pot2(\"web1<br/>10.192.0.3\")\n"));
+        assert!(uut.contains("    host --- bridge0\n"));
+        assert!(uut.contains("    public_bridge --- pot2\n"));
+    }
+
+    #[test]
+    fn get_network_usage_001() {
+        let (conf, bridges, pots) = topology_test_data();
+        let uut = get_network_usage(&conf, &bridges, &pots);
+        assert_eq!(uut.total, 256);
+        // network, broadcast and gateway
+        assert_eq!(uut.reserved, 3);
+        // web1 and lonely, db1 is inside the bridge
+        assert_eq!(uut.allocated, 2);
+        assert_eq!(uut.bridges, 8);
+        assert_eq!(uut.free, 243);
+    }
+
+    #[test]
+    fn get_bridge_usage_001() {
+        let (_conf, bridges, pots) = topology_test_data();
+        let uut = get_bridge_usage(&bridges[0], &pots);
+        assert_eq!(uut.total, 8);
+        assert_eq!(uut.reserved, 3);
+        assert_eq!(uut.allocated, 1);
+        assert_eq!(uut.bridges, 0);
+        assert_eq!(uut.free, 4);
+        assert!((uut.used_percent() - 50.0).abs() < f64::EPSILON);
+        let opt = UsageOpt {
+            warning: 50,
+            min_free: None,
+        };
+        assert!(uut.is_exceeding(&opt));
+        let opt = UsageOpt {
+            warning: 60,
+            min_free: Some(4),
+        };
+        assert!(!uut.is_exceeding(&opt));
+        let opt = UsageOpt {
+            warning: 60,
+            min_free: Some(5),
+        };
+        assert!(uut.is_exceeding(&opt));
+    }
+
+    #[test]
+    fn get_vpn_overlaps_001() {
+        let conf = PotSystemConfig {
+            network: "10.192.0.0/24".parse().unwrap(),
+            gateway: "10.192.0.1".parse().unwrap(),
+            vpn_networks: vec![
+                "10.192.0.128/25".parse().unwrap(),
+                "10.8.0.0/24".parse().unwrap(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            get_vpn_overlaps(&conf),
+            vec!["10.192.0.128/25".parse::<IpNet>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn explain_001() {
+        let (conf, bridges, pots) = topology_test_data();
+        let mut space = AddressSpace::with_pots(&conf, &bridges, &pots);
+        space.add_forbidden("10.192.0.128/25".parse().unwrap());
+        let uut = explain(&space, &"10.192.0.3".parse().unwrap());
+        assert_eq!(
+            uut,
+            vec![
+                "10.192.0.3 is used by the pot web1",
+                "nearest free address: 10.192.0.2"
+            ]
+        );
+        let uut = explain(&space, &"10.192.0.19".parse().unwrap());
+        assert_eq!(
+            uut[0],
+            "10.192.0.19 is used by the network of the bridge test-bridge"
+        );
+        assert_eq!(uut[1], "nearest free address: 10.192.0.15");
+        let uut = explain(&space, &"10.192.0.200".parse().unwrap());
+        assert_eq!(
+            uut,
+            vec![
+                "10.192.0.200 is inside the forbidden network 10.192.0.128/25",
+                "nearest free address: 10.192.0.127"
+            ]
+        );
+        let uut = explain(&space, &"10.192.0.4".parse().unwrap());
+        assert_eq!(uut, vec!["10.192.0.4 is available in 10.192.0.0/24"]);
+    }
+
+    #[test]
+    fn whois_address_001() {
+        let (conf, bridges, pots) = topology_test_data();
+        let mut reservations = BTreeMap::new();
+        reservations.insert("10.192.0.5".parse().unwrap(), "printer".to_string());
+        reservations.insert("10.192.0.20".parse().unwrap(), "switch".to_string());
+        let mut aliases = BTreeMap::new();
+        aliases.insert(
+            "proxy".to_string(),
+            vec!["10.1.0.7".parse().unwrap(), "10.192.0.40".parse().unwrap()],
+        );
+        let uut = |ip: &str| {
+            whois_address(
+                &conf,
+                &bridges,
+                &pots,
+                &aliases,
+                &reservations,
+                &ip.parse().unwrap(),
+            )
+        };
+        assert_eq!(uut("10.192.0.3"), "10.192.0.3: the pot web1");
+        assert_eq!(uut("10.192.0.40"), "10.192.0.40: the alias pot proxy");
+        assert_eq!(uut("10.1.0.7"), "10.1.0.7: the alias pot proxy");
+        assert_eq!(uut("10.192.0.1"), "10.192.0.1: the default gateway");
+        assert_eq!(
+            uut("10.192.0.5"),
+            "10.192.0.5: a manual reservation (printer)"
+        );
+        assert_eq!(
+            uut("10.192.0.18"),
+            "10.192.0.18: the pot db1 (bridge test-bridge)"
+        );
+        assert_eq!(
+            uut("10.192.0.20"),
+            "10.192.0.20: a manual reservation (switch) (bridge test-bridge)"
+        );
+        assert_eq!(
+            uut("10.192.0.21"),
+            "10.192.0.21: free (bridge test-bridge 10.192.0.16/29)"
+        );
+        assert_eq!(uut("10.192.0.4"), "10.192.0.4: free (10.192.0.0/24)");
+        assert_eq!(
+            uut("10.193.0.4"),
+            "10.193.0.4: Address 10.193.0.4 outside the network 10.192.0.0/24"
+        );
+    }
+
+    #[test]
+    fn whois_pot_001() {
+        let (mut conf, bridges, pots) = topology_test_data();
+        conf.dns = Some(pot_rs::PotDnsConfig {
+            pot_name: "web1".to_string(),
+            ip: "10.192.0.3".parse().unwrap(),
+        });
+        let running = vec!["db1".to_string()];
+        let mut aliases = BTreeMap::new();
+        aliases.insert(
+            "proxy".to_string(),
+            vec!["10.1.0.7".parse().unwrap(), "fdf1::7".parse().unwrap()],
+        );
+        let uut = whois_pot(&conf, &bridges, &pots, &aliases, &running, "db1");
+        assert_eq!(
+            uut,
+            Some(vec![
+                "pot db1".to_string(),
+                "\tnetwork type: private-bridge".to_string(),
+                "\taddress: 10.192.0.18".to_string(),
+                "\tbridge: test-bridge (10.192.0.16/29)".to_string(),
+                "\trunning: yes".to_string(),
+            ])
+        );
+        let uut = whois_pot(&conf, &bridges, &pots, &aliases, &running, "web1").unwrap();
+        assert_eq!(uut[3], "\tDNS pot: 10.192.0.3");
+        assert_eq!(uut[4], "\trunning: no");
+        let uut = whois_pot(&conf, &bridges, &pots, &aliases, &running, "proxy").unwrap();
+        assert_eq!(
+            uut,
+            vec![
+                "pot proxy".to_string(),
+                "\tnetwork type: alias".to_string(),
+                "\taddress: 10.1.0.7".to_string(),
+                "\taddress: fdf1::7".to_string(),
+                "\trunning: no".to_string(),
+            ]
+        );
+        assert_eq!(
+            whois_pot(&conf, &bridges, &pots, &aliases, &running, "web2"),
+            None
+        );
+    }
+
+    #[test]
+    fn export_document_001() {
+        let (conf, bridges, pots) = topology_test_data();
+        let mut reservations = BTreeMap::new();
+        reservations.insert("10.192.0.5".parse().unwrap(), "printer".to_string());
+        let uut = export_document(&conf, &bridges, &pots, &reservations);
+        assert_eq!(uut.version, EXPORT_VERSION);
+        assert_eq!(uut.bridges.len(), 1);
+        assert_eq!(uut.pots[0].name, "db1");
+        assert_eq!(uut.pots[0].bridge, Some("test-bridge".to_string()));
+        assert_eq!(uut.pots[1].name, "lonely");
+        assert_eq!(uut.pots[1].bridge, None);
+        let json = serde_json::to_string(&uut).unwrap();
+        assert!(json.contains("\"network\":\"10.192.0.16/29\""));
+        assert!(json.contains("\"reservations\":{\"10.192.0.5\":\"printer\"}"));
+        let doc: NetworkDocument = serde_json::from_str(&json).unwrap();
+        assert_eq!(doc, uut);
+    }
 
     #[test]
-    fn get_network_size_000() {
-        let uut = get_network_size(2);
-        assert_eq!(uut, Some(2));
+    fn check_import_001() {
+        let (conf, bridges, pots) = topology_test_data();
+        let reservations = BTreeMap::new();
+        // the same state has only conflicts on the pot names
+        let doc = export_document(&conf, &bridges, &pots, &reservations);
+        let uut = check_import(&doc, &conf, &bridges, &pots, &reservations);
+        assert_eq!(uut.len(), 3);
+        assert_eq!(uut[0], "pot db1: already present on this host");
+        let doc = NetworkDocument {
+            bridges: vec![
+                BridgeDocument {
+                    name: "test-bridge".to_string(),
+                    network: "10.192.0.16/28".parse().unwrap(),
+                    gateway: "10.192.0.17".parse().unwrap(),
+                },
+                BridgeDocument {
+                    name: "new-bridge".to_string(),
+                    network: "10.192.0.0/28".parse().unwrap(),
+                    gateway: "10.192.0.1".parse().unwrap(),
+                },
+                BridgeDocument {
+                    name: "other-bridge".to_string(),
+                    network: "10.192.0.32/28".parse().unwrap(),
+                    gateway: "10.192.0.33".parse().unwrap(),
+                },
+            ],
+            pots: vec![
+                PotDocument {
+                    name: "web2".to_string(),
+                    network_type: "public-bridge".to_string(),
+                    ip: Some("10.192.0.3".parse().unwrap()),
+                    bridge: None,
+                },
+                PotDocument {
+                    name: "db2".to_string(),
+                    network_type: "private-bridge".to_string(),
+                    ip: Some("10.192.0.19".parse().unwrap()),
+                    bridge: Some("test-bridge".to_string()),
+                },
+                PotDocument {
+                    name: "db3".to_string(),
+                    network_type: "private-bridge".to_string(),
+                    ip: Some("10.192.0.34".parse().unwrap()),
+                    bridge: Some("other-bridge".to_string()),
+                },
+                PotDocument {
+                    name: "web3".to_string(),
+                    network_type: "public-bridge".to_string(),
+                    ip: Some("10.192.0.4".parse().unwrap()),
+                    bridge: None,
+                },
+            ],
+            reservations: [("10.192.0.130".parse().unwrap(), "printer".to_string())]
+                .into_iter()
+                .collect(),
+            ..doc
+        };
+        let uut = check_import(&doc, &conf, &bridges, &pots, &reservations);
+        assert_eq!(
+            uut,
+            vec![
+                "bridge test-bridge: network 10.192.0.16/28, but 10.192.0.16/29 on this host",
+                "bridge new-bridge: network 10.192.0.0/28 not available on this host",
+                "pot web2: Address 10.192.0.3 already in use (web1)",
+                "reservation 10.192.0.130 (printer): used by the pot lonely",
+            ]
+        );
     }
+
     #[test]
-    fn get_network_size_001() {
-        let uut = get_network_size(5);
-        assert_eq!(uut, Some(3));
+    fn check_import_002() {
+        let (conf, bridges, pots) = topology_test_data();
+        let reservations = BTreeMap::new();
+        let pot = |name: &str, network_type: &str, ip: &str, bridge: Option<&str>| PotDocument {
+            name: name.to_string(),
+            network_type: network_type.to_string(),
+            ip: Some(ip.parse().unwrap()),
+            bridge: bridge.map(str::to_string),
+        };
+        let bridge = |name: &str, network: &str, gateway: &str| BridgeDocument {
+            name: name.to_string(),
+            network: network.parse().unwrap(),
+            gateway: gateway.parse().unwrap(),
+        };
+        let doc = NetworkDocument {
+            bridges: vec![
+                bridge("new-bridge", "10.192.0.32/28", "10.192.0.33"),
+                bridge("test-bridge", "10.192.0.16/29", "10.192.0.18"),
+                bridge("far-bridge", "10.192.0.48/28", "10.192.1.49"),
+                bridge("broadcast-bridge", "10.192.0.64/28", "10.192.0.79"),
+            ],
+            pots: vec![
+                pot("web2", "public-bridge", "10.192.0.5", None),
+                pot("web3", "public-bridge", "10.192.0.5", None),
+                pot("web4", "public-bridge", "10.192.0.40", None),
+                pot("db2", "private-bridge", "10.192.0.34", Some("new-bridge")),
+                pot("db3", "private-bridge", "10.192.0.34", Some("new-bridge")),
+                pot("proxy", "alias", "192.168.1.5", None),
+                pot("proxy2", "alias", "10.192.0.5", None),
+            ],
+            reservations: [
+                ("10.192.0.5".parse().unwrap(), "printer".to_string()),
+                ("10.192.0.18".parse().unwrap(), "scanner".to_string()),
+                ("10.192.0.34".parse().unwrap(), "nas".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            ..export_document(&conf, &bridges, &pots, &reservations)
+        };
+        let uut = check_import(&doc, &conf, &bridges, &pots, &reservations);
+        assert_eq!(
+            uut,
+            vec![
+                "bridge test-bridge: gateway 10.192.0.18, but 10.192.0.17 on this host",
+                "bridge far-bridge: gateway 10.192.1.49 not a host of the network 10.192.0.48/28",
+                "bridge broadcast-bridge: gateway 10.192.0.79 not a host of the network 10.192.0.64/28",
+                "pot web3: Address 10.192.0.5 already in use (web2)",
+                "pot web4: Address 10.192.0.40 already in use (new-bridge bridge - allocated address)",
+                "pot db3: Address 10.192.0.34 already in use (db2)",
+                "reservation 10.192.0.5 (printer): used by the pot web2",
+                "reservation 10.192.0.18 (scanner): used by the pot db1",
+                "reservation 10.192.0.34 (nas): used by the pot db2",
+            ]
+        );
     }
+
     #[test]
-    fn get_network_size_002() {
-        let uut = get_network_size(7);
-        assert_eq!(uut, Some(4));
+    fn get_ordered_hosts_001() {
+        let network: IpNet = "10.192.0.0/29".parse().unwrap();
+        let uut: Vec<IpAddr> = get_ordered_hosts(&network, Strategy::Lowest, None).collect();
+        assert_eq!(uut.len(), 6);
+        assert_eq!(uut[0], "10.192.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(uut[5], "10.192.0.6".parse::<IpAddr>().unwrap());
+        let uut: Vec<IpAddr> = get_ordered_hosts(&network, Strategy::Highest, None).collect();
+        assert_eq!(uut.len(), 6);
+        assert_eq!(uut[0], "10.192.0.6".parse::<IpAddr>().unwrap());
+        assert_eq!(uut[5], "10.192.0.1".parse::<IpAddr>().unwrap());
+        let uut: BTreeSet<IpAddr> = get_ordered_hosts(&network, Strategy::Random, None).collect();
+        assert_eq!(uut.len(), 6);
     }
 
     #[test]
-    fn get_prefix_length_000() {
-        let ip_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
-        let uut = get_prefix_length(2, &ip_addr);
-        assert_eq!(uut, Some(30));
+    fn get_ordered_hosts_002() {
+        let network: IpNet = "10.192.0.0/24".parse().unwrap();
+        let uut: Vec<IpAddr> =
+            get_ordered_hosts(&network, Strategy::Sticky, Some("web1")).collect();
+        let uut2: Vec<IpAddr> =
+            get_ordered_hosts(&network, Strategy::Sticky, Some("web1")).collect();
+        assert_eq!(uut, uut2);
+        assert_eq!(uut.len(), 254);
+        assert_eq!(uut.iter().collect::<BTreeSet<_>>().len(), 254);
+        let first = get_sticky_seed("web1") % 254;
+        assert_eq!(uut[0], HostRange::new(&network).unwrap().get(first));
     }
+
     #[test]
-    fn get_prefix_length_001() {
-        let ip_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
-        let uut = get_prefix_length(5, &ip_addr);
-        assert_eq!(uut, Some(29));
+    fn host_range_iter_from_001() {
+        let network: IpNet = "fdf1:186e:49e6:76d8::/126".parse().unwrap();
+        let uut: Vec<IpAddr> = HostRange::new(&network).unwrap().iter_from(6).collect();
+        let expected: Vec<IpAddr> = [
+            "fdf1:186e:49e6:76d8::2",
+            "fdf1:186e:49e6:76d8::3",
+            "fdf1:186e:49e6:76d8::",
+            "fdf1:186e:49e6:76d8::1",
+        ]
+        .iter()
+        .map(|x| x.parse().unwrap())
+        .collect();
+        assert_eq!(uut, expected);
     }
+
     #[test]
-    fn get_prefix_length_002() {
-        let ip_addr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
-        let uut = get_prefix_length(9, &ip_addr);
-        assert_eq!(uut, Some(28));
+    fn update_released_001() {
+        let ip1: IpAddr = "10.192.0.3".parse().unwrap();
+        let ip2: IpAddr = "10.192.0.4".parse().unwrap();
+        let ip3: IpAddr = "10.192.0.5".parse().unwrap();
+        let mut previous = BTreeMap::new();
+        previous.insert(ip1, "pot1".to_string());
+        previous.insert(ip2, "pot2".to_string());
+        let mut current = BTreeMap::new();
+        current.insert(ip2, "pot2".to_string());
+        let mut released = BTreeMap::new();
+        released.insert(ip3, 10);
+        update_released(&previous, &current, &mut released, 100, 50);
+        assert_eq!(released.len(), 1);
+        assert_eq!(released.get(&ip1), Some(&100));
+        // ip1 is allocated again
+        update_released(&current, &previous, &mut released, 110, 50);
+        assert!(released.is_empty());
     }
+
     #[test]
-    fn get_prefix_length_010() {
-        let ip_addr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
-        let uut = get_prefix_length(2, &ip_addr);
-        assert_eq!(uut, Some(126));
+    fn select_addresses_001() {
+        let network: IpNet = "10.192.0.0/28".parse().unwrap();
+        let mut space = AddressSpace::new(network);
+        space.insert("10.192.0.1".parse().unwrap(), AddressRole::Gateway);
+        space.insert(
+            "10.192.0.4".parse().unwrap(),
+            AddressRole::Pot("a".to_string()),
+        );
+        space.insert(
+            "10.192.0.8".parse().unwrap(),
+            AddressRole::Pot("b".to_string()),
+        );
+        let mut skipped = BTreeSet::new();
+        skipped.insert("10.192.0.2".parse().unwrap());
+        let ordered = || get_ordered_hosts(&network, Strategy::Lowest, None);
+        let uut = select_addresses(ordered(), &space, &skipped, 3, false);
+        let expected: Vec<IpAddr> = ["10.192.0.3", "10.192.0.5", "10.192.0.6"]
+            .iter()
+            .map(|x| x.parse().unwrap())
+            .collect();
+        assert_eq!(uut, expected);
+        let uut = select_addresses(ordered(), &space, &skipped, 3, true);
+        let expected: Vec<IpAddr> = ["10.192.0.5", "10.192.0.6", "10.192.0.7"]
+            .iter()
+            .map(|x| x.parse().unwrap())
+            .collect();
+        assert_eq!(uut, expected);
+        let uut = select_addresses(ordered(), &space, &skipped, 7, true);
+        assert!(uut.len() < 7);
+        let uut = select_addresses(
+            get_ordered_hosts(&network, Strategy::Highest, None),
+            &space,
+            &skipped,
+            2,
+            true,
+        );
+        let expected: Vec<IpAddr> = ["10.192.0.14", "10.192.0.13"]
+            .iter()
+            .map(|x| x.parse().unwrap())
+            .collect();
+        assert_eq!(uut, expected);
+        space.add_forbidden("10.192.0.4/30".parse().unwrap());
+        let uut = select_addresses(ordered(), &space, &skipped, 3, false);
+        let expected: Vec<IpAddr> = ["10.192.0.3", "10.192.0.9", "10.192.0.10"]
+            .iter()
+            .map(|x| x.parse().unwrap())
+            .collect();
+        assert_eq!(uut, expected);
     }
+
     #[test]
-    fn get_prefix_length_011() {
-        let ip_addr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
-        let uut = get_prefix_length(5, &ip_addr);
-        assert_eq!(uut, Some(125));
+    fn parse_setting_001() {
+        let uut = parse_setting("POT_NETWORK=10.192.0.0/10");
+        assert!(uut.is_ok());
+        assert_eq!(
+            uut.unwrap(),
+            ("POT_NETWORK".to_string(), "10.192.0.0/10".to_string())
+        );
+        assert!(parse_setting("POT_NETWORK").is_err());
+        assert!(parse_setting("FOO=bar").is_err());
     }
+
     #[test]
-    fn get_prefix_length_012() {
-        let ip_addr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
-        let uut = get_prefix_length(9, &ip_addr);
-        assert_eq!(uut, Some(124));
+    fn config_show_001() {
+        use pot_rs::{ConfSource, ConfValue, DiscardedValue};
+        let mut provenance = ConfProvenance::default();
+        provenance.values.insert(
+            "POT_NETWORK".to_string(),
+            ConfValue {
+                value: "10.192.0.0/10".to_string(),
+                source: ConfSource::DefaultFile,
+            },
+        );
+        provenance.values.insert(
+            "POT_GATEWAY".to_string(),
+            ConfValue {
+                value: "10.192.0.1".to_string(),
+                source: ConfSource::CommandLine,
+            },
+        );
+        provenance.discarded.push(DiscardedValue {
+            key: "POT_DNS_IP".to_string(),
+            value: "192.168.0.2".to_string(),
+            source: ConfSource::DefaultFile,
+            reason: "the default DNS IP is outside the pot network".to_string(),
+        });
+        let uut = config_show(&provenance);
+        assert!(uut.starts_with(
+            "# precedence: environment < pot.default.conf < pot.conf < command line\n"
+        ));
+        assert!(uut.contains("POT_NETWORK=10.192.0.0/10 # pot.default.conf\n"));
+        assert!(uut.contains("POT_GATEWAY=10.192.0.1 # command line\n"));
+        assert!(uut.contains("# POT_ZFS_ROOT is not set\n"));
+        assert!(uut.ends_with(
+            "# discarded values:\n# POT_DNS_IP=192.168.0.2 from pot.default.conf: the default DNS IP is outside the pot network\n"
+        ));
     }
 }
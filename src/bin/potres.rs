@@ -0,0 +1,205 @@
+use anyhow::Result;
+use log::{info, trace, warn};
+use pot_rs::command::{CommandRunner, SystemRunner};
+use pot_rs::rctl::{get_jail_rules, get_jail_usage, RctlRule, ResourceUsage};
+use pot_rs::{get_running_pot_list, PotSystemConfig};
+use structopt::StructOpt;
+use structopt_flags::{LogLevel, QuietVerbose};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "potres")]
+struct Opt {
+    #[structopt(flatten)]
+    verbose: QuietVerbose,
+    #[structopt(subcommand)]
+    subcommand: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Show the resource limits and the current usage of the running pots
+    #[structopt(name = "show")]
+    Show(ShowOpt),
+}
+
+#[derive(Debug, StructOpt)]
+struct ShowOpt {
+    /// Show only this pot
+    #[structopt(short = "j", long = "pot-name")]
+    pot_name: Option<String>,
+}
+
+/// The resources reported for each pot
+const RESOURCES: [&str; 4] = ["memoryuse", "pcpu", "maxproc", "openfiles"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ResourceReport {
+    resource: String,
+    usage: Option<u64>,
+    limit: Option<u64>,
+}
+
+impl ResourceReport {
+    fn used_percent(&self) -> Option<u64> {
+        match (self.usage, self.limit) {
+            (Some(usage), Some(limit)) if limit > 0 => Some(usage * 100 / limit),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ResourceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let to_string = |x: Option<u64>| x.map_or_else(|| "NA".to_string(), |x| x.to_string());
+        write!(
+            f,
+            "{}: {} / {}",
+            self.resource,
+            to_string(self.usage),
+            to_string(self.limit)
+        )?;
+        if let Some(percent) = self.used_percent() {
+            write!(f, " ({}%)", percent)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the usage with the limits; the strictest deny rule is the limit of a resource,
+/// the other actions (log, devctl, signals) only notify
+fn get_resource_reports(usage: &ResourceUsage, rules: &[RctlRule]) -> Vec<ResourceReport> {
+    RESOURCES
+        .iter()
+        .map(|resource| ResourceReport {
+            resource: resource.to_string(),
+            usage: usage.get(*resource).copied(),
+            limit: rules
+                .iter()
+                .filter(|rule| rule.resource == *resource && rule.action == "deny")
+                .filter_map(|rule| rule.amount)
+                .min(),
+        })
+        .collect()
+}
+
+/// The sum of the memory limits
+fn get_memory_commitment(reports: &[(String, Vec<ResourceReport>)]) -> u64 {
+    reports
+        .iter()
+        .flat_map(|(_pot_name, pot_reports)| pot_reports)
+        .filter(|report| report.resource == "memoryuse")
+        .filter_map(|report| report.limit)
+        .sum()
+}
+
+fn get_physmem(runner: &dyn CommandRunner) -> Result<u64> {
+    let output = runner.run("/sbin/sysctl", &["-n", "hw.physmem"])?;
+    Ok(output.trim().parse()?)
+}
+
+fn get_pot_reports(
+    runner: &dyn CommandRunner,
+    pot_names: &[String],
+) -> Result<Vec<(String, Vec<ResourceReport>)>> {
+    let mut result = Vec::new();
+    for pot_name in pot_names {
+        let usage = get_jail_usage(runner, pot_name)?;
+        let rules = get_jail_rules(runner, pot_name)?;
+        result.push((pot_name.clone(), get_resource_reports(&usage, &rules)));
+    }
+    Ok(result)
+}
+
+fn show(runner: &dyn CommandRunner, conf: &PotSystemConfig, cmd_opt: &ShowOpt) -> Result<()> {
+    let mut pot_names = get_running_pot_list(conf);
+    pot_names.sort();
+    if let Some(pot_name) = &cmd_opt.pot_name {
+        pot_names.retain(|x| x == pot_name);
+    }
+    let reports = get_pot_reports(runner, &pot_names)?;
+    for (pot_name, pot_reports) in &reports {
+        println!("pot {}:", pot_name);
+        for report in pot_reports {
+            println!("\t{}", report);
+        }
+    }
+    let physmem = get_physmem(runner)?;
+    let committed = get_memory_commitment(&reports);
+    info!("memory limits: {} physical memory: {}", committed, physmem);
+    if committed > physmem {
+        warn!(
+            "memory overcommitted: the limits sum up to {}, but the physical memory is {}",
+            committed, physmem
+        );
+        println!("memory overcommitted: {} / {}", committed, physmem);
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    opt.verbose.set_log_level();
+    trace!("potres start");
+
+    let conf = PotSystemConfig::from_system()?;
+    match &opt.subcommand {
+        Command::Show(cmd_opt) => show(&SystemRunner, &conf, cmd_opt)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pot_rs::rctl::{parse_rules, parse_usage};
+
+    struct TestRunner;
+
+    impl CommandRunner for TestRunner {
+        fn run(&self, program: &str, args: &[&str]) -> pot_rs::Result<String> {
+            match (program, args) {
+                ("/sbin/sysctl", ["-n", "hw.physmem"]) => Ok("2147483648\n".to_string()),
+                ("/usr/bin/rctl", ["-u", _]) => {
+                    Ok("memoryuse=536870912\npcpu=12\nmaxproc=20\n".to_string())
+                }
+                ("/usr/bin/rctl", ["jail:web1:"]) => {
+                    Ok("jail:web1:memoryuse:deny=1073741824\n".to_string())
+                }
+                ("/usr/bin/rctl", ["jail:db1:"]) => Ok(
+                    "jail:db1:memoryuse:deny=2147483648\njail:db1:memoryuse:devctl=1073741824\n"
+                        .to_string(),
+                ),
+                _ => Ok(String::new()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_resource_reports() {
+        let usage = parse_usage("memoryuse=536870912\npcpu=150\nopenfiles=64\n").unwrap();
+        let rules =
+            parse_rules("jail:web1:memoryuse:deny=1073741824\njail:web1:pcpu:deny=200\n").unwrap();
+        let result = get_resource_reports(&usage, &rules);
+        assert_eq!(result.len(), 4);
+        assert_eq!(
+            result[0].to_string(),
+            "memoryuse: 536870912 / 1073741824 (50%)"
+        );
+        assert_eq!(result[1].to_string(), "pcpu: 150 / 200 (75%)");
+        assert_eq!(result[2].to_string(), "maxproc: NA / NA");
+        assert_eq!(result[3].to_string(), "openfiles: 64 / NA");
+    }
+
+    #[test]
+    fn test_get_memory_commitment() {
+        let pot_names = vec!["db1".to_string(), "web1".to_string(), "other".to_string()];
+        let reports = get_pot_reports(&TestRunner, &pot_names);
+        assert!(reports.is_ok());
+        let reports = reports.unwrap();
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0].1[0].limit, Some(2147483648));
+        assert_eq!(reports[2].1[0].limit, None);
+        assert_eq!(get_memory_commitment(&reports), 3221225472);
+        assert_eq!(get_physmem(&TestRunner).unwrap(), 2147483648);
+    }
+}